@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::iter::Iterator;
 use regex::Regex;
-use synacor::WORD;
+use synacor::{LineTable,WORD};
 use synacor::opcode::Opcode;
 use super::types::{Argument,Token,TokenType};
 
@@ -10,7 +10,7 @@ pub fn remove_comments(source: String) -> String {
         static ref comment_rx: Regex = Regex::new(r"^\s*;.*$").unwrap();
     }
     source.lines()
-    .filter(|l| !comment_rx.is_match(l)) // remove all comment-only lines
+    .map(|l| if comment_rx.is_match(l) { "" } else { l }) // blank comment-only lines, keeping line numbers
     .map(|l| l[0..l.find(';').unwrap_or(l.len())].trim())
     .collect::<Vec<_>>()
     .join("\n")
@@ -20,7 +20,9 @@ pub fn split_to_lines<'l>(source: &'l String) -> Vec<&'l str> {
     source.lines().collect::<Vec<&'l str>>()
 }
 
-pub fn tokenize<'t>(source_lines: Vec<&'t str>) -> Vec<Token<'t>> {
+/// Splits source lines into tokens. Lines that can't be parsed are
+/// collected into the error, one per line.
+pub fn tokenize<'t>(source_lines: Vec<&'t str>) -> Result<Vec<Token<'t>>, String> {
     lazy_static! {
         static ref instruction_rx: Regex = Regex::new(r"(?x)
             ^\s*
@@ -37,10 +39,17 @@ pub fn tokenize<'t>(source_lines: Vec<&'t str>) -> Vec<Token<'t>> {
             "(?P<data>.*[^\\])"
             (?:,(?P<end_chars>\d+(?:,\d+)*))?
             $"#).unwrap();
+        static ref data_rx: Regex = Regex::new(r"(?x)
+            ^\s*
+            (?:(?P<label>[a-z][\w_]+)\s+)?
+            dw\s+
+            (?P<words>\d+(?:\s*,\s*\d+)*)
+            $").unwrap();
         static ref label_only_rx: Regex = Regex::new(r"^\s*([a-z][\w_]+):\s*$").unwrap();
     }
 
     let mut tokens: Vec<Token> = Vec::new();
+    let mut errors: Vec<String> = Vec::new();
     let mut last_label: Option<&str> = None;
 
     for idx in 0..source_lines.len() {
@@ -70,6 +79,7 @@ pub fn tokenize<'t>(source_lines: Vec<&'t str>) -> Vec<Token<'t>> {
                 tok_type: TokenType::Instruction,
                 label: label,
                 offset: off,
+                line: idx + 1,
                 opcode: opcode,
                 args: [arg_a, arg_b, arg_c],
                 data: vec![]
@@ -77,7 +87,7 @@ pub fn tokenize<'t>(source_lines: Vec<&'t str>) -> Vec<Token<'t>> {
         } else if declaration_rx.is_match(l) {
             if last_label.is_some() {
                 // oh shit son
-                println!("standalone label before data declaration is not supported");
+                errors.push(format!("Line #{}: standalone label before data declaration is not supported", idx+1));
                 last_label = None;
             }
 
@@ -97,48 +107,71 @@ pub fn tokenize<'t>(source_lines: Vec<&'t str>) -> Vec<Token<'t>> {
                 tok_type: TokenType::DataDeclaration,
                 label: Some(label),
                 offset: off,
+                line: idx + 1,
+                opcode: None,
+                args: [None; 3],
+                data: data
+            });
+        } else if data_rx.is_match(l) {
+            let caps = data_rx.captures(l).unwrap();
+            let words = caps.name("words").unwrap().as_str().split(',').map(|n| n.trim().parse::<WORD>().ok());
+            let data = match words.collect::<Option<Vec<WORD>>>() {
+                Some(data) => data,
+                None => {
+                    errors.push(format!("Unable to parse line #{}: {}", idx+1, l));
+                    continue;
+                }
+            };
+            let label = last_label.take().or_else(|| caps.name("label").map(|l| l.as_str()));
+
+            let off = tokens.last().map(|t| t.offset + t.size()).unwrap_or(0);
+            tokens.push(Token {
+                tok_type: TokenType::DataDeclaration,
+                label: label,
+                offset: off,
+                line: idx + 1,
                 opcode: None,
                 args: [None; 3],
                 data: data
             });
         } else {
-            println!("Unable to parse line #{}: {}", idx+1, l);
+            errors.push(format!("Unable to parse line #{}: {}", idx+1, l));
             continue;
         }
     }
 
-    tokens
+    if errors.is_empty() { Ok(tokens) } else { Err(errors.join("\n")) }
 }
 
-pub fn resolve_labels(mut tokens: Vec<Token>) -> Vec<Token> {
-    let label_map: HashMap<String,usize> = tokens.iter().filter(|t| t.label.is_some()).map(|t| (t.label.unwrap().to_string(), t.offset)).collect();
-    tokens.iter_mut().for_each(|tok| {
-        tok.args = [
-            match tok.args[0] {
-                Some(Argument::Label(l)) => Some(Argument::Number(*label_map.get(&l[..]).unwrap() as u16)),
-                a => a,
-            },
-            match tok.args[1] {
-                Some(Argument::Label(l)) => Some(Argument::Number(*label_map.get(&l[..]).unwrap() as u16)),
-                a => a,
-            },
-            match tok.args[2] {
-                Some(Argument::Label(l)) => Some(Argument::Number(*label_map.get(&l[..]).unwrap() as u16)),
-                a => a,
+pub fn label_map(tokens: &[Token]) -> HashMap<String,usize> {
+    tokens.iter().filter(|t| t.label.is_some()).map(|t| (t.label.unwrap().to_string(), t.offset)).collect()
+}
+
+/// Where each token came from, as a line table for `file`.
+pub fn line_table(tokens: &[Token], file: &str) -> LineTable {
+    let mut table = LineTable::new();
+    tokens.iter().for_each(|t| table.add(t.offset, t.size(), file, t.line));
+    table
+}
+
+/// Replaces label arguments with the labels' offsets. Fails on the first
+/// label that is never defined.
+pub fn resolve_labels(mut tokens: Vec<Token>) -> Result<Vec<Token>, String> {
+    let label_map = label_map(&tokens);
+    for tok in tokens.iter_mut() {
+        for arg in tok.args.iter_mut() {
+            if let Some(Argument::Label(l)) = *arg {
+                match label_map.get(l) {
+                    Some(&offset) => *arg = Some(Argument::Number(offset as u16)),
+                    None => return Err(format!("Line #{}: undefined label '{}'", tok.line, l)),
+                }
             }
-        ];
+        }
         tok.label = None;
-    });
-    tokens
+    }
+    Ok(tokens)
 }
 
-pub fn convert_to_bytes(tokens: Vec<Token>) -> Vec<u8> {
-    let mut bytes: Vec<u8> = Vec::new();
-    for tok in tokens {
-        let words = tok.as_words();
-        let tok_bytes = words.iter().flat_map(|w| vec![(w & 0xFF) as u8, (w >> 8) as u8]).collect::<Vec<u8>>();
-        println!("{:?} => {:?}", tok, tok_bytes);
-        bytes.extend(tok_bytes);
-    }
-    bytes
+pub fn convert_to_bytes(words: &[WORD]) -> Vec<u8> {
+    words.iter().flat_map(|w| vec![(w & 0xFF) as u8, (w >> 8) as u8]).collect()
 }
\ No newline at end of file
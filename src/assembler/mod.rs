@@ -1,17 +1,19 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::Path;
+use synacor::{LineTable,Symbols,WORD};
 
 mod assembly_steps;
-mod disassembly_steps;
 mod types;
 
-pub fn assemble(source: String, dest_filename: &str) {
+pub fn assemble(source: String, source_filename: &str, dest_filename: &str) {
     let source_len = source.len();
-    let source_without_comments = assembly_steps::remove_comments(source);
-    let source_lines            = assembly_steps::split_to_lines(&source_without_comments);
-    let tokens                  = assembly_steps::tokenize(source_lines);
-    let tokens                  = assembly_steps::resolve_labels(tokens);
-    let bytes                   = assembly_steps::convert_to_bytes(tokens);
+    let assembly = match assemble_program(&source, source_filename) {
+        Ok(assembly) => assembly,
+        Err(e) => return eprintln!("Unable to assemble {}:\n{}", source_filename, e),
+    };
+    let bytes = assembly_steps::convert_to_bytes(&assembly.words);
 
     for c in bytes.chunks(20) {
         c.chunks(2).for_each(|cc| print!("{:#04X}{:02X} ", cc[0], cc[1]));
@@ -21,16 +23,43 @@ pub fn assemble(source: String, dest_filename: &str) {
     let mut f = File::create(dest_filename).unwrap();
     f.write_all(&bytes[..]);
     println!("Assembled {} bytes of source to {} bytes of binary", source_len, bytes.len());
+
+    let sym_filename = Path::new(dest_filename).with_extension("sym");
+    match Symbols::from_labels(&assembly.labels).save(&sym_filename) {
+        Ok(_) => println!("Wrote {} labels to {}", assembly.labels.len(), sym_filename.display()),
+        Err(e) => println!("Unable to write symbols to {}: {}", sym_filename.display(), e),
+    }
+
+    let lines_filename = Path::new(dest_filename).with_extension("lines");
+    match assembly.lines.save(&lines_filename) {
+        Ok(_) => println!("Wrote line table to {}", lines_filename.display()),
+        Err(e) => println!("Unable to write line table to {}: {}", lines_filename.display(), e),
+    }
 }
 
-pub fn disassemble(bin: Vec<u8>, dest_filename: &str) {
-    let words        = disassembly_steps::convert_to_words(bin);
-    let tokens       = disassembly_steps::convert_to_tokens(words);
-    let source_lines = disassembly_steps::convert_to_instructions(tokens);
-    let source_str   = source_lines.into_iter().collect::<String>();
+/// A program assembled in memory, with what a debugger needs to map
+/// addresses back to the source.
+pub struct Assembly {
+    pub words: Vec<WORD>,
+    pub labels: HashMap<String,usize>,
+    pub lines: LineTable,
+}
 
-    let mut f = File::create(dest_filename).unwrap();
-    write!(f, "{}", source_str);
+/// Assembles `source`, read from `filename`, without writing anything out.
+/// Fails with every line that couldn't be parsed, or an undefined label.
+/// The line table names the source by its file name alone, as it sits
+/// beside the binary; `LineTable::resolve_files` makes that a path.
+pub fn assemble_program(source: &str, filename: &str) -> Result<Assembly, String> {
+    let source_without_comments = assembly_steps::remove_comments(source.to_string());
+    let source_lines            = assembly_steps::split_to_lines(&source_without_comments);
+    let tokens                  = assembly_steps::tokenize(source_lines)?;
+    let labels                  = assembly_steps::label_map(&tokens);
+    let source_name             = Path::new(filename).file_name().and_then(|n| n.to_str()).unwrap_or(filename);
+    let lines                   = assembly_steps::line_table(&tokens, source_name);
+    let tokens                  = assembly_steps::resolve_labels(tokens)?;
+    let words                   = tokens.iter().flat_map(|t| t.as_words()).collect();
+
+    Ok(Assembly { words: words, labels: labels, lines: lines })
 }
 
 /*
@@ -60,6 +60,7 @@ pub struct Token<'t> {
     pub tok_type: TokenType,
     pub label: Option<&'t str>,
     pub offset: usize,
+    pub line: usize, // 1-based source line, 0 if not from source
     pub opcode: Option<Opcode>,
     pub args: [Option<Argument<'t>>; 3],
     pub data: Vec<WORD>
@@ -73,6 +74,7 @@ impl<'t> Token<'t> {
             tok_type: TokenType::DataDeclaration,
             label: Some(&format!("dd{}", num_dd_tokens)[..]),
             offset: 0,
+            line: 0,
             opcode: None,
             args: [None, None, None],
             data: vec![],
@@ -87,6 +89,7 @@ impl<'t> Token<'t> {
             tok_type: TokenType::Instruction,
             label: None,
             offset: 0,
+            line: 0,
             opcode: None,
             args: [None, None, None],
             data: vec![],
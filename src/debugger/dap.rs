@@ -0,0 +1,606 @@
+//! A Debug Adapter Protocol server over stdio (`synacor --dap`), so any
+//! DAP-capable editor can debug Synacor programs.
+//!
+//! `launch` takes `program`, either a binary or `.asm` source (assembled on
+//! the fly). Source breakpoints, line numbers and stepping by line work for
+//! source, and for binaries with the assembler's `.lines` file beside them,
+//! plus optional `input`, a file fed to the program as keyboard input, and
+//! `stopOnEntry`. The program's output appears as `output` events.
+//!
+//! Memory references are word addresses written as `0x0ABC`; `readMemory`
+//! returns words little-endian, as in a binary. In the debug console a line
+//! starting with `>` is typed into the program, anything else is evaluated
+//! as a debugger expression (see `expr`).
+//!
+//! Requests are read on a thread of their own, so a `pause` or `disconnect`
+//! interrupts a running program instead of waiting behind it.
+
+use std::fs::File;
+use std::io::{self,BufRead,Read,Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::sync::mpsc;
+use std::thread;
+use json::{self,JsonValue};
+use assembler;
+use synacor::{parse_number,BufferConsole,LineTable,StopReason,Symbols,Vm,WORD};
+use super::{disasm,expr,format_value,Breakpoint,Debugger};
+
+const THREAD_ID: i64 = 1;
+const REGISTERS_REF: usize = 1;
+const STACK_REF: usize = 2;
+const MEMORY_REF: usize = 3;
+// memory is shown in pages, referenced as MEMORY_PAGE_REF + page number
+const MEMORY_PAGE_REF: usize = 1000;
+const MEMORY_PAGE_SIZE: usize = 256;
+
+pub struct Server {
+    out: Box<dyn Write>,
+    interrupt: Arc<AtomicBool>, // the launched VM's interrupt flag
+    debugger: Option<Debugger>,
+    console: BufferConsole,
+    stop_on_entry: bool,
+    source_breakpoints: Vec<usize>,
+    instruction_breakpoints: Vec<usize>,
+    seq: i64,
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn format_word(w: WORD) -> String {
+    format!("{:#06X} ({})", w, w)
+}
+
+/// Reads one `Content-Length` framed message. Returns None at end of input.
+fn read_message(input: &mut dyn BufRead) -> io::Result<Option<JsonValue>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 { return Ok(None); }
+        let header = header.trim();
+        if header.is_empty() {
+            if length.is_some() { break; }
+            continue;
+        }
+        if header.to_lowercase().starts_with("content-length:") {
+            length = header["content-length:".len()..].trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = vec![0u8; length.unwrap()];
+    input.read_exact(&mut body)?;
+    json::parse(&String::from_utf8_lossy(&body))
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+/// Serves one debug session on stdin and stdout.
+pub fn serve() -> io::Result<()> {
+    let mut server = Server::new(Box::new(io::stdout()));
+    let (send, requests) = mpsc::channel();
+    let interrupt = server.interrupt.clone();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        loop {
+            let request = read_message(&mut input);
+            let stops = match request {
+                Ok(Some(ref r)) => match r["command"].as_str() {
+                    Some("pause") | Some("disconnect") | Some("terminate") => true,
+                    _ => false,
+                },
+                _ => true,
+            };
+            // the program has to stop before the request can be answered
+            if stops { interrupt.store(true, Ordering::SeqCst); }
+            let more = match request { Ok(Some(_)) => true, _ => false };
+            if send.send(request).is_err() || !more { break; }
+        }
+    });
+
+    for request in requests {
+        match request? {
+            Some(request) => if !server.handle(&request)? { break; },
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+impl Server {
+    /// A server that writes its messages to `out`.
+    fn new(out: Box<dyn Write>) -> Server {
+        Server {
+            out: out,
+            interrupt: Arc::new(AtomicBool::new(false)),
+            debugger: None,
+            console: BufferConsole::new(),
+            stop_on_entry: false,
+            source_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            seq: 1,
+        }
+    }
+
+    fn send(&mut self, mut message: JsonValue) -> io::Result<()> {
+        message["seq"] = self.seq.into();
+        self.seq += 1;
+        let body = message.dump();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()
+    }
+
+    fn event(&mut self, event: &str, body: JsonValue) -> io::Result<()> {
+        self.send(object!{ "type" => "event", "event" => event, "body" => body })
+    }
+
+    fn debugger(&mut self) -> Result<&mut Debugger,String> {
+        self.debugger.as_mut().ok_or("no program has been launched".to_string())
+    }
+
+    /// Answers one request. Returns false once the session is over.
+    fn handle(&mut self, request: &JsonValue) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or("").to_string();
+        let args = &request["arguments"];
+
+        let result = match &command[..] {
+            "initialize"                => Ok(object!{
+                "supportsConfigurationDoneRequest" => true,
+                "supportsDisassembleRequest" => true,
+                "supportsInstructionBreakpoints" => true,
+                "supportsReadMemoryRequest" => true,
+                "supportsConditionalBreakpoints" => true,
+                "supportsEvaluateForHovers" => true,
+                "supportsSteppingGranularity" => true
+            }),
+            "launch"                    => self.launch(args),
+            "setBreakpoints"            => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setExceptionBreakpoints"   => Ok(object!{ "breakpoints" => array![] }),
+            "threads"                   => Ok(object!{ "threads" => array![object!{ "id" => THREAD_ID, "name" => "synacor" }] }),
+            "stackTrace"                => self.stack_trace(),
+            "scopes"                    => Ok(object!{ "scopes" => array![
+                object!{ "name" => "Registers", "variablesReference" => REGISTERS_REF, "expensive" => false },
+                object!{ "name" => "Stack", "variablesReference" => STACK_REF, "expensive" => false },
+                object!{ "name" => "Memory", "variablesReference" => MEMORY_REF, "expensive" => true }
+            ] }),
+            "variables"                 => self.variables(args),
+            "evaluate"                  => self.evaluate(args),
+            "disassemble"               => self.disassemble(args),
+            "readMemory"                => self.read_memory(args),
+            "configurationDone" | "continue" | "next" | "stepIn" | "stepOut" | "pause" => Ok(JsonValue::new_object()),
+            "disconnect" | "terminate"  => Ok(JsonValue::new_object()),
+            _                           => Err(format!("unsupported request '{}'", command)),
+        };
+
+        let mut response = object!{
+            "type" => "response",
+            "request_seq" => (request["seq"].clone()),
+            "command" => (command.clone()),
+            "success" => (result.is_ok())
+        };
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = message.into(),
+        }
+        self.send(response)?;
+
+        // anything that runs the program does so after answering
+        match &command[..] {
+            "launch" if self.debugger.is_some() => self.event("initialized", JsonValue::new_object())?,
+            "configurationDone" if self.stop_on_entry => self.stopped("entry", None)?,
+            "configurationDone" | "continue" => self.run(|d| d.resume(None), "breakpoint")?,
+            // by source line where there is a line table, unless asked for instructions
+            "next" | "stepIn" => {
+                let by_line = args["granularity"].as_str() != Some("instruction") &&
+                    self.debugger.as_ref().map_or(false, |d| !d.vm().line_table().is_empty());
+                match (&command[..], by_line) {
+                    ("next", true)  => self.run(|d| d.step_lines(1, true), "step")?,
+                    ("next", false) => self.run(|d| d.next(), "step")?,
+                    (_, true)       => self.run(|d| d.step_lines(1, false), "step")?,
+                    (_, false)      => self.run(|d| d.resume(Some(1)), "step")?,
+                }
+            },
+            "stepOut"  => self.run(|d| d.finish(), "step")?,
+            "disconnect" | "terminate" => return Ok(false),
+            _ => {},
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &JsonValue) -> Result<JsonValue,String> {
+        let path = args["program"].as_str().ok_or("launch needs a 'program'".to_string())?.to_string();
+        let mut contents = Vec::new();
+        File::open(&path).and_then(|mut f| f.read_to_end(&mut contents)).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+
+        let (words, symbols, lines) = if path.ends_with(".asm") {
+            let mut assembly = assembler::assemble_program(&String::from_utf8_lossy(&contents), &path)?;
+            if let Some(dir) = Path::new(&path).parent() { assembly.lines.resolve_files(dir); }
+            (assembly.words, Symbols::from_labels(&assembly.labels), assembly.lines)
+        } else {
+            // pick up the labels and line table the assembler or disassembler left beside it
+            let symbols = Symbols::load(Path::new(&path).with_extension("sym")).unwrap_or(Symbols::new());
+            let lines = LineTable::load(Path::new(&path).with_extension("lines")).unwrap_or(LineTable::new());
+            (contents.chunks(2).map(|c| *c.get(1).unwrap_or(&0) as WORD * 256 + c[0] as WORD).collect(), symbols, lines)
+        };
+
+        if let Some(input) = args["input"].as_str() {
+            let mut text = String::new();
+            File::open(input).and_then(|mut f| f.read_to_string(&mut text)).map_err(|e| format!("Unable to read {}: {}", input, e))?;
+            self.console.push_input(&text);
+        }
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+
+        let mut vm = Vm::with_console(Box::new(self.console.clone()));
+        vm.set_interrupt_handle(self.interrupt.clone());
+        vm.load_memory(words).map_err(|e| e.to_string())?;
+        vm.set_symbols(symbols);
+        vm.set_line_table(lines);
+        self.debugger = Some(Debugger::new(vm));
+        Ok(JsonValue::new_object())
+    }
+
+    /// Replaces every breakpoint in the launched source file.
+    fn set_breakpoints(&mut self, args: &JsonValue) -> Result<JsonValue,String> {
+        let path = args["source"]["path"].as_str().unwrap_or("").to_string();
+        let mut ids = Vec::new();
+        let mut result = JsonValue::new_array();
+        {
+            let old = &self.source_breakpoints;
+            let debugger = self.debugger.as_mut().ok_or("no program has been launched".to_string())?;
+            debugger.entries.retain(|e| !old.contains(&e.id));
+
+            for bp in args["breakpoints"].members() {
+                let line = bp["line"].as_usize().unwrap_or(0);
+                let place = debugger.vm().line_table().address_of(&path, line);
+                let condition = match bp["condition"].as_str() {
+                    Some(src) => Some((src.to_string(), expr::parse(src)?)),
+                    None => None,
+                };
+                let _ = result.push(match place {
+                    Some((addr, line)) => {
+                        let id = debugger.add_entry(Breakpoint::Instruction(addr as u16), condition);
+                        ids.push(id);
+                        object!{ "id" => id, "verified" => true, "line" => line, "instructionReference" => (format!("{:#06X}", addr)) }
+                    },
+                    None => object!{ "verified" => false, "message" => "no code at or after this line" },
+                });
+            }
+        }
+        self.source_breakpoints = ids;
+        Ok(object!{ "breakpoints" => result })
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &JsonValue) -> Result<JsonValue,String> {
+        let mut ids = Vec::new();
+        let mut result = JsonValue::new_array();
+        {
+            let old = &self.instruction_breakpoints;
+            let debugger = self.debugger.as_mut().ok_or("no program has been launched".to_string())?;
+            debugger.entries.retain(|e| !old.contains(&e.id));
+
+            for bp in args["breakpoints"].members() {
+                let base = bp["instructionReference"].as_str().and_then(parse_number).map(|a| a as i64);
+                let condition = match bp["condition"].as_str() {
+                    Some(src) => Some((src.to_string(), expr::parse(src)?)),
+                    None => None,
+                };
+                let _ = result.push(match base.map(|b| b + bp["offset"].as_i64().unwrap_or(0)) {
+                    Some(addr) if addr >= 0 && addr <= 0x7FFF => {
+                        let id = debugger.add_entry(Breakpoint::Instruction(addr as u16), condition);
+                        ids.push(id);
+                        object!{ "id" => id, "verified" => true }
+                    },
+                    _ => object!{ "verified" => false },
+                });
+            }
+        }
+        self.instruction_breakpoints = ids;
+        Ok(object!{ "breakpoints" => result })
+    }
+
+    fn frame(&self, id: usize, addr: usize) -> JsonValue {
+        let mut frame = object!{
+            "id" => id,
+            "name" => (format!("{:#06X}", addr)),
+            "line" => 0,
+            "column" => 0,
+            "instructionPointerReference" => (format!("{:#06X}", addr))
+        };
+        if let Some(name) = self.debugger.as_ref().and_then(|d| d.vm().symbols().describe(addr)) { frame["name"] = name.into(); }
+        if let Some((file, line)) = self.debugger.as_ref().and_then(|d| d.vm().line_table().location(addr)) {
+            frame["line"] = line.into();
+            frame["column"] = 1.into();
+            frame["source"] = object!{ "path" => file };
+        }
+        frame
+    }
+
+    fn stack_trace(&mut self) -> Result<JsonValue,String> {
+        let (pc, callers) = {
+            let debugger = self.debugger()?;
+            (debugger.vm().cpu().pc(), debugger.vm().backtrace().iter().map(|f| f.caller).collect::<Vec<_>>())
+        };
+        let mut frames = JsonValue::new_array();
+        let _ = frames.push(self.frame(0, pc));
+        for (n, &caller) in callers.iter().enumerate() {
+            let _ = frames.push(self.frame(n + 1, caller));
+        }
+        Ok(object!{ "stackFrames" => (frames.clone()), "totalFrames" => (frames.len()) })
+    }
+
+    fn variables(&mut self, args: &JsonValue) -> Result<JsonValue,String> {
+        let reference = args["variablesReference"].as_usize().unwrap_or(0);
+        let vm = self.debugger()?.vm();
+        let mut vars = JsonValue::new_array();
+        {
+            let mut add = |name: String, value: String, reference: usize| {
+                let _ = vars.push(object!{ "name" => name, "value" => value, "variablesReference" => reference });
+            };
+            match reference {
+                REGISTERS_REF => {
+                    add("pc".to_string(), format!("{:#06X}", vm.cpu().pc()), 0);
+                    for (r, &v) in vm.cpu().registers().iter().enumerate() {
+                        add(format!("r{}", r), format_word(v), 0);
+                    }
+                },
+                STACK_REF => {
+                    for (n, &w) in vm.stack().words().iter().rev().enumerate() {
+                        add(format!("[{}]", n), format_word(w), 0);
+                    }
+                },
+                MEMORY_REF => {
+                    for page in 0..vm.memory().words().len() / MEMORY_PAGE_SIZE {
+                        let start = page * MEMORY_PAGE_SIZE;
+                        add(format!("{:#06X}-{:#06X}", start, start + MEMORY_PAGE_SIZE - 1), String::new(), MEMORY_PAGE_REF + page);
+                    }
+                },
+                r if r >= MEMORY_PAGE_REF => {
+                    let start = (r - MEMORY_PAGE_REF) * MEMORY_PAGE_SIZE;
+                    for addr in start..start + MEMORY_PAGE_SIZE {
+                        if let Some(w) = vm.memory().read(addr) { add(format!("{:#06X}", addr), format_word(w), 0); }
+                    }
+                },
+                _ => {},
+            }
+        }
+        Ok(object!{ "variables" => vars })
+    }
+
+    fn evaluate(&mut self, args: &JsonValue) -> Result<JsonValue,String> {
+        let text = args["expression"].as_str().unwrap_or("").to_string();
+        if args["context"].as_str() == Some("repl") && text.starts_with('>') {
+            self.console.push_input(&format!("{}\n", text[1..].trim()));
+            return Ok(object!{ "result" => "", "variablesReference" => 0 });
+        }
+        let value = expr::parse(&text)?.eval(self.debugger()?.vm())?;
+        Ok(object!{ "result" => (format_value(value)), "variablesReference" => 0 })
+    }
+
+    fn disassemble(&mut self, args: &JsonValue) -> Result<JsonValue,String> {
+        let base = args["memoryReference"].as_str().and_then(parse_number).ok_or("bad memoryReference".to_string())?;
+        let base = (base as i64 + args["offset"].as_i64().unwrap_or(0) / 2).max(0) as usize;
+        let skip = args["instructionOffset"].as_i64().unwrap_or(0);
+        let count = args["instructionCount"].as_usize().unwrap_or(0);
+
+        let listing = {
+            let memory = self.debugger()?.vm().memory();
+            let start = if skip < 0 {
+                disasm::start_before(memory, base, (-skip) as usize)
+            } else {
+                disasm::listing(memory, base, skip as usize + 1).last().map(|&(a, _)| a).unwrap_or(base)
+            };
+            disasm::listing(memory, start, count)
+        };
+
+        let mut instructions = JsonValue::new_array();
+        for (addr, text) in listing {
+            let mut ins = object!{ "address" => (format!("{:#06X}", addr)), "instruction" => text };
+            let vm = self.debugger()?.vm();
+            if let Some(symbol) = vm.symbols().describe(addr) { ins["symbol"] = symbol.into(); }
+            if let Some((file, line)) = vm.line_table().location(addr) {
+                ins["line"] = line.into();
+                ins["location"] = object!{ "path" => file };
+            }
+            let _ = instructions.push(ins);
+        }
+        Ok(object!{ "instructions" => instructions })
+    }
+
+    fn read_memory(&mut self, args: &JsonValue) -> Result<JsonValue,String> {
+        let base = args["memoryReference"].as_str().and_then(parse_number).ok_or("bad memoryReference".to_string())?;
+        let start = (base as i64 * 2 + args["offset"].as_i64().unwrap_or(0)).max(0) as usize;
+        let count = args["count"].as_usize().unwrap_or(0);
+        let memory = self.debugger()?.vm().memory();
+
+        let bytes = (start..start + count)
+            .map(|b| memory.read(b / 2).map(|w| if b % 2 == 0 { w as u8 } else { (w >> 8) as u8 }))
+            .take_while(|b| b.is_some())
+            .map(|b| b.unwrap())
+            .collect::<Vec<_>>();
+        Ok(object!{ "address" => (format!("{:#06X}", start / 2)), "data" => (base64(&bytes)), "unreadableBytes" => (count - bytes.len()) })
+    }
+
+    /// Runs the program with `go`, then reports what it printed and why it stopped.
+    fn run<F: FnOnce(&mut Debugger) -> StopReason>(&mut self, go: F, reason: &str) -> io::Result<()> {
+        let stop = match self.debugger.as_mut() {
+            Some(debugger) => go(debugger),
+            None => return Ok(()),
+        };
+
+        let output = self.console.take_output();
+        if !output.is_empty() {
+            self.event("output", object!{ "category" => "stdout", "output" => output })?;
+        }
+
+        match stop {
+            StopReason::Halted => {
+                self.event("exited", object!{ "exitCode" => 0 })?;
+                self.event("terminated", JsonValue::new_object())
+            },
+            StopReason::Error(e) => self.stopped("exception", Some(e.to_string())),
+            StopReason::WaitingForInput => self.stopped("pause", Some("Waiting for input; type '>' and a line in the debug console".to_string())),
+            StopReason::BreakpointHit => {
+                let hit_watch = self.debugger.as_ref().map_or(false, |d| !d.hits.is_empty());
+                self.stopped(if hit_watch { "data breakpoint" } else { reason }, None)
+            },
+            StopReason::Interrupted => self.stopped("pause", None),
+            StopReason::BudgetExhausted => self.stopped(reason, None),
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        let mut body = object!{ "reason" => reason, "threadId" => THREAD_ID, "allThreadsStopped" => true };
+        if let Some(text) = text { body["text"] = text.into(); }
+        self.event("stopped", body)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::io::{self,Cursor,Write};
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool,Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use json::JsonValue;
+    use super::{read_message,Server};
+
+    /// Collects what the server sends, so it can be read back while the
+    /// server still owns it.
+    #[derive(Clone,Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.borrow_mut().write(buf) }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    impl Output {
+        /// Takes the messages sent since the last call.
+        fn messages(&self) -> Vec<JsonValue> {
+            let mut input = Cursor::new(self.0.borrow_mut().split_off(0));
+            let mut messages = Vec::new();
+            while let Some(message) = read_message(&mut input).unwrap() { messages.push(message); }
+            messages
+        }
+    }
+
+    fn frame(headers: &str, body: &str) -> String {
+        format!("{}Content-Length: {}\r\n\r\n{}", headers, body.len(), body)
+    }
+
+    #[test]
+    fn test_read_message() {
+        let text = format!("{}{}", frame("Content-Type: application/json\r\n", "{\"seq\":1}"), frame("\r\n", "[]").to_lowercase());
+        let mut input = Cursor::new(text.into_bytes());
+        assert_eq!(Some(object!{ "seq" => 1 }), read_message(&mut input).unwrap());
+        assert_eq!(Some(array![]), read_message(&mut input).unwrap());
+        assert_eq!(None, read_message(&mut input).unwrap());
+
+        let mut input = Cursor::new(frame("", "{x}").into_bytes());
+        assert_eq!(io::ErrorKind::InvalidData, read_message(&mut input).unwrap_err().kind());
+        let mut input = Cursor::new(b"Content-Length: 10\r\n\r\n{}".to_vec());
+        assert_eq!(io::ErrorKind::UnexpectedEof, read_message(&mut input).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_handle() {
+        let output = Output::default();
+        let mut server = Server::new(Box::new(output.clone()));
+        let mut request = |command: &str, arguments: JsonValue| {
+            let request = object!{ "seq" => 1, "type" => "request", "command" => command, "arguments" => arguments };
+            let more = server.handle(&request).unwrap();
+            (more, output.messages())
+        };
+
+        let (more, messages) = request("initialize", object!{});
+        assert!(more);
+        assert_eq!(1, messages.len());
+        assert_eq!("response", messages[0]["type"]);
+        assert_eq!(1, messages[0]["request_seq"]);
+        assert_eq!("initialize", messages[0]["command"]);
+        assert_eq!(true, messages[0]["success"]);
+        assert_eq!(true, messages[0]["body"]["supportsDisassembleRequest"]);
+
+        let (_, messages) = request("stackTrace", object!{});
+        assert_eq!(false, messages[0]["success"]);
+        assert_eq!("no program has been launched", messages[0]["message"]);
+        let (_, messages) = request("bogus", object!{});
+        assert_eq!("unsupported request 'bogus'", messages[0]["message"]);
+
+        // out 'A'; halt
+        let program = ::std::env::temp_dir().join("synacor_test_dap.bin");
+        ::std::fs::write(&program, [19, 0, 65, 0, 0, 0]).unwrap();
+        let (_, messages) = request("launch", object!{ "program" => program.to_str().unwrap() });
+        ::std::fs::remove_file(&program).unwrap();
+        assert_eq!(true, messages[0]["success"]);
+        assert_eq!("initialized", messages[1]["event"]);
+
+        let source = ::std::env::temp_dir().join("synacor_test_dap.asm");
+        ::std::fs::write(&source, "jmp nowhere\n").unwrap();
+        let (_, messages) = request("launch", object!{ "program" => source.to_str().unwrap() });
+        ::std::fs::remove_file(&source).unwrap();
+        assert_eq!(false, messages[0]["success"]);
+        assert_eq!("Line #1: undefined label 'nowhere'", messages[0]["message"]);
+
+        let (_, messages) = request("stackTrace", object!{});
+        assert_eq!("0x0000", messages[0]["body"]["stackFrames"][0]["instructionPointerReference"]);
+
+        let (_, messages) = request("configurationDone", object!{});
+        let events = messages[1..].iter().map(|m| m["event"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(vec!["output", "exited", "terminated"], events);
+        assert_eq!("A", messages[1]["body"]["output"]);
+
+        let (more, messages) = request("disconnect", object!{});
+        assert!(!more);
+        assert_eq!(true, messages[0]["success"]);
+    }
+
+    #[test]
+    fn test_pause() {
+        let output = Output::default();
+        let mut server = Server::new(Box::new(output.clone()));
+        let request = |command: &str, arguments: JsonValue| object!{ "seq" => 1, "type" => "request", "command" => command, "arguments" => arguments };
+
+        // jmp 0
+        let program = ::std::env::temp_dir().join("synacor_test_dap_pause.bin");
+        ::std::fs::write(&program, [6, 0, 0, 0]).unwrap();
+        server.handle(&request("launch", object!{ "program" => program.to_str().unwrap() })).unwrap();
+        ::std::fs::remove_file(&program).unwrap();
+        output.messages();
+
+        // what serve's reader does when a pause comes in, repeated in case
+        // it lands before the run has started
+        let interrupt = server.interrupt.clone();
+        let done = Arc::new(AtomicBool::new(false));
+        let running = done.clone();
+        let pause = thread::spawn(move || while !running.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(20));
+            interrupt.store(true, Ordering::SeqCst);
+        });
+        server.handle(&request("continue", object!{})).unwrap();
+        done.store(true, Ordering::SeqCst);
+        pause.join().unwrap();
+        let messages = output.messages();
+        assert_eq!("stopped", messages[1]["event"]);
+        assert_eq!("pause", messages[1]["body"]["reason"]);
+    }
+}
\ No newline at end of file
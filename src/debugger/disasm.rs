@@ -0,0 +1,145 @@
+use std::collections::BTreeSet;
+use std::io::{self,Write};
+use synacor::{Symbols,WORD};
+use synacor::memory::Memory;
+use synacor::opcode::Opcode;
+
+fn word_at(memory: &Memory, addr: usize) -> WORD { memory.read(addr).unwrap_or(0) }
+
+/// Formats an operand the way the assembler writes it.
+pub fn operand(w: WORD) -> String {
+    match w {
+        w if w < 0x8000 => format!("#{}", w),
+        w if w < 0x8008 => format!("r{}", w - 0x8000),
+        w               => format!("?{:#06X}", w),
+    }
+}
+
+/// Disassembles the instruction at `addr`, returning its text and length.
+/// Anything that is not a valid opcode comes out as a one-word `dw`.
+pub fn instruction_at(memory: &Memory, addr: usize) -> (String, usize) {
+    let code = word_at(memory, addr);
+    let opcode = match Opcode::decode(code) {
+        Ok(opcode) => opcode,
+        Err(_) => return (format!("dw {:#06X}", code), 1),
+    };
+    let mut text = opcode.mnemonic().to_string();
+    for off in 1..(opcode.argc() + 1) {
+        text.push(' ');
+        text.push_str(&operand(word_at(memory, addr + off)));
+    }
+    if opcode == Opcode::Out {
+        let c = word_at(memory, addr + 1);
+        if c >= 0x20 && c < 0x7F { text.push_str(&format!(" ; '{}'", c as u8 as char)); }
+        if c == 10 { text.push_str(" ; '\\n'"); }
+    }
+    (text, 1 + opcode.argc())
+}
+
+/// Disassembles `count` instructions starting at `addr`.
+pub fn listing(memory: &Memory, addr: usize, count: usize) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut a = addr;
+    for _ in 0..count {
+        if a > 0x7FFF { break; }
+        let (text, len) = instruction_at(memory, a);
+        lines.push((a, text));
+        a += len;
+    }
+    lines
+}
+
+/// Picks a start address a little before `pc` that decodes into an
+/// instruction boundary at `pc`, so a listing can show some context.
+/// Falls back to `pc` itself when nothing before it lines up.
+pub fn start_before(memory: &Memory, pc: usize, max_back: usize) -> usize {
+    let earliest = if pc > max_back * 4 { pc - max_back * 4 } else { 0 };
+    for start in earliest..pc {
+        let mut a = start;
+        let mut count = 0;
+        while a < pc {
+            a += instruction_at(memory, a).1;
+            count += 1;
+        }
+        if a == pc && count <= max_back { return start; }
+    }
+    pc
+}
+
+/// Where the jump or call at `addr` goes, when its target is a number
+/// rather than a register.
+pub fn target(memory: &Memory, addr: usize) -> Option<usize> {
+    let arg = match Opcode::decode(word_at(memory, addr)).ok()? {
+        Opcode::Jmp | Opcode::Call => word_at(memory, addr + 1),
+        Opcode::Jt | Opcode::Jf    => word_at(memory, addr + 2),
+        _ => return None,
+    };
+    if arg < 0x8000 { Some(arg as usize) } else { None }
+}
+
+/// Makes up labels for the first `len` words: `sub_XXXX` for call targets
+/// and `loc_XXXX` for jump targets. Data is decoded as code on the way, so
+/// a few labels may be nonsense.
+pub fn generate_labels(memory: &Memory, len: usize) -> Symbols {
+    let mut calls = BTreeSet::new();
+    let mut jumps = BTreeSet::new();
+    let mut addr = 0;
+    while addr < len {
+        if let Some(t) = target(memory, addr).filter(|&t| t < len) {
+            if Opcode::decode(word_at(memory, addr)) == Ok(Opcode::Call) { calls.insert(t); } else { jumps.insert(t); }
+        }
+        addr += instruction_at(memory, addr).1;
+    }
+
+    let mut symbols = Symbols::new();
+    for &t in calls.iter() { symbols.insert(&format!("sub_{:04X}", t), t); }
+    for &t in jumps.difference(&calls) { symbols.insert(&format!("loc_{:04X}", t), t); }
+    symbols
+}
+
+/// The instruction at `addr` the way the assembler reads it: every operand
+/// that isn't a register as a plain number, even ones the VM would reject.
+fn source_at(memory: &Memory, addr: usize) -> (String, usize) {
+    let code = word_at(memory, addr);
+    let opcode = match Opcode::decode(code) {
+        Ok(opcode) => opcode,
+        Err(_) => return (format!("dw {}", code), 1),
+    };
+    let mut text = opcode.mnemonic().to_string();
+    for off in 1..(opcode.argc() + 1) {
+        match word_at(memory, addr + off) {
+            w if w >= 0x8000 && w < 0x8008 => text.push_str(&format!(" r{}", w - 0x8000)),
+            w                              => text.push_str(&format!(" #{}", w)),
+        }
+    }
+    (text, 1 + opcode.argc())
+}
+
+/// Writes the first `len` words as assembly, with a line for each label
+/// in `symbols` and jump and call targets given by label where there is one.
+/// Assembling the result gives back the same words.
+pub fn write_source(memory: &Memory, len: usize, symbols: &Symbols, out: &mut dyn Write) -> io::Result<()> {
+    let mut addr = 0;
+    while addr < len {
+        if let Some(name) = symbols.name(addr) { writeln!(out, "{}:", name)?; }
+        let (mut text, mut size) = source_at(memory, addr);
+        // a label inside the instruction means this is data running into
+        // code, and one running past the end is data too
+        if addr + size > len || (addr + 1..addr + size).any(|a| symbols.name(a).is_some()) {
+            text = format!("dw {}", word_at(memory, addr));
+            size = 1;
+        }
+        if let Some(name) = target(memory, addr).filter(|_| size > 1).and_then(|t| symbols.name(t)) {
+            let cut = text.rfind(' ').unwrap();
+            text = format!("{} {}", &text[..cut], name);
+        }
+        // keep the character comment `instruction_at` gives an `out`
+        let (shown, _) = instruction_at(memory, addr);
+        match shown.find(" ; ").filter(|_| size > 1) {
+            Some(i) => writeln!(out, "    {:<32} ; {:#06X} {}", text, addr, &shown[i + 3..])?,
+            None => writeln!(out, "    {:<32} ; {:#06X}", text, addr)?,
+        }
+        addr += size;
+    }
+    Ok(())
+}
\ No newline at end of file
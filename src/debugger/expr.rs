@@ -0,0 +1,340 @@
+//! The debugger's expression language, used for breakpoint conditions,
+//! `display` and `print`.
+//!
+//! ```text
+//! r7 != 0 && [0x0AAC] == 25
+//! pc == 0x178B && stack.depth > 3
+//! ```
+//!
+//! Operands are numbers (decimal or 0x hex), registers `r0`-`r7`, `pc`,
+//! memory `[expr]`, `stack.depth`, `stack[n]` (0 is the top of the
+//! stack) and the program's labels, when symbols are loaded. Operators,
+//! loosest first: `||`, `&&`, `|`, `^`, `&`, the comparisons, `+ -`,
+//! `* / %`, and the unary `! - ~`. Values are signed
+//! 64 bit; comparisons and logic give 0 or 1, and anything non-zero is true.
+//!
+//! Tracepoint messages embed expressions in text: `"r0={r0} top={stack[0]:x}"`.
+
+use std::fmt;
+use std::mem;
+use synacor::Vm;
+use synacor::cpu::NUM_REGISTERS;
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum BinOp { Or, And, BitOr, BitXor, BitAnd, Eq, Ne, Lt, Le, Gt, Ge, Add, Sub, Mul, Div, Rem }
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum UnOp { Not, Neg, BitNot }
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Expr {
+    Num(i64),
+    Reg(usize),
+    Pc,
+    Symbol(String),
+    StackDepth,
+    Stack(Box<Expr>),
+    Mem(Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone,Debug,PartialEq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Sym(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Num(n)       => write!(f, "{}", n),
+            Token::Ident(ref s) => write!(f, "{}", s),
+            Token::Sym(s)       => write!(f, "{}", s),
+        }
+    }
+}
+
+// longest first, so "<=" is not read as "<" then "="
+const SYMBOLS: [&'static str; 23] = [
+    "||", "&&", "==", "!=", "<=", ">=",
+    "|", "^", "&", "<", ">", "+", "-", "*", "/", "%", "!", "~", "(", ")", "[", "]", ".",
+];
+
+fn tokenize(src: &str) -> Result<Vec<Token>,String> {
+    let mut tokens = Vec::new();
+    let chars = src.chars().collect::<Vec<_>>();
+    let mut i = 0;
+    'outer: while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() { i += 1; continue; }
+
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') { i += 1; }
+            let word = chars[start..i].iter().collect::<String>();
+            if c.is_ascii_digit() {
+                let n = ::synacor::parse_number(&word).ok_or(format!("bad number '{}'", word))?;
+                tokens.push(Token::Num(n as i64));
+            } else {
+                tokens.push(Token::Ident(word));
+            }
+            continue;
+        }
+
+        for sym in SYMBOLS.iter() {
+            let len = sym.len();
+            if i + len <= chars.len() && chars[i..i + len].iter().collect::<String>() == *sym {
+                tokens.push(Token::Sym(sym));
+                i += len;
+                continue 'outer;
+            }
+        }
+        return Err(format!("unexpected '{}'", c));
+    }
+    Ok(tokens)
+}
+
+// binary operators by precedence level, loosest first
+const LEVELS: [&'static [(&'static str, BinOp)]; 8] = [
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[("|", BinOp::BitOr)],
+    &[("^", BinOp::BitXor)],
+    &[("&", BinOp::BitAnd)],
+    &[("==", BinOp::Eq), ("!=", BinOp::Ne), ("<", BinOp::Lt), ("<=", BinOp::Le), (">", BinOp::Gt), (">=", BinOp::Ge)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Rem)],
+];
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> { self.tokens.get(self.pos) }
+
+    fn next(&mut self) -> Result<Token,String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or("unexpected end of expression".to_string())?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, sym: &'static str) -> Result<(),String> {
+        match self.next()? {
+            Token::Sym(s) if s == sym => Ok(()),
+            t => Err(format!("expected '{}', found '{}'", sym, t)),
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr,String> {
+        if level == LEVELS.len() { return self.unary(); }
+
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Some(&Token::Sym(s)) => LEVELS[level].iter().find(|&&(sym, _)| sym == s).map(|&(_, op)| op),
+                _ => None,
+            };
+            match op {
+                Some(op) => {
+                    self.pos += 1;
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                },
+                None => return Ok(lhs),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr,String> {
+        let op = match self.peek() {
+            Some(&Token::Sym("!")) => Some(UnOp::Not),
+            Some(&Token::Sym("-")) => Some(UnOp::Neg),
+            Some(&Token::Sym("~")) => Some(UnOp::BitNot),
+            _ => None,
+        };
+        match op {
+            Some(op) => { self.pos += 1; Ok(Expr::Unary(op, Box::new(self.unary()?))) },
+            None => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr,String> {
+        match self.next()? {
+            Token::Num(n) => Ok(Expr::Num(n)),
+            Token::Sym("(") => {
+                let e = self.binary(0)?;
+                self.expect(")")?;
+                Ok(e)
+            },
+            Token::Sym("[") => {
+                let e = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Mem(Box::new(e)))
+            },
+            Token::Ident(ref s) if s == "pc" => Ok(Expr::Pc),
+            Token::Ident(ref s) if s == "stack" => match self.next()? {
+                Token::Sym(".") => match self.next()? {
+                    Token::Ident(ref s) if s == "depth" => Ok(Expr::StackDepth),
+                    t => Err(format!("unknown stack property '{}'", t)),
+                },
+                Token::Sym("[") => {
+                    let e = self.binary(0)?;
+                    self.expect("]")?;
+                    Ok(Expr::Stack(Box::new(e)))
+                },
+                t => Err(format!("expected '.depth' or '[' after 'stack', found '{}'", t)),
+            },
+            Token::Ident(ref s) if s.len() > 1 && s.starts_with('r') && s[1..].bytes().all(|b| b.is_ascii_digit()) => match s[1..].parse::<usize>() {
+                Ok(r) if r < NUM_REGISTERS => Ok(Expr::Reg(r)),
+                _ => Err(format!("unknown register '{}'", s)),
+            },
+            Token::Ident(s) => Ok(Expr::Symbol(s)),
+            t => Err(format!("unexpected '{}'", t)),
+        }
+    }
+}
+
+/// Parses a complete expression.
+pub fn parse(src: &str) -> Result<Expr,String> {
+    let mut parser = Parser { tokens: tokenize(src)?, pos: 0 };
+    let expr = parser.binary(0)?;
+    match parser.peek() {
+        Some(t) => Err(format!("unexpected '{}' after expression", t)),
+        None => Ok(expr),
+    }
+}
+
+impl Expr {
+    /// Evaluates against the current state of `vm`. Fails on reads outside
+    /// memory or the stack, and on division by zero.
+    pub fn eval(&self, vm: &Vm) -> Result<i64,String> {
+        Ok(match *self {
+            Expr::Num(n) => n,
+            Expr::Reg(r) => vm.cpu().register_get(r) as i64,
+            Expr::Pc => vm.cpu().pc() as i64,
+            Expr::Symbol(ref name) => vm.symbols().address(name).ok_or(format!("no symbol '{}'", name))? as i64,
+            Expr::StackDepth => vm.stack().len() as i64,
+            Expr::Stack(ref e) => {
+                let n = e.eval(vm)?;
+                let words = vm.stack().words();
+                if n < 0 || n as usize >= words.len() { return Err(format!("stack[{}] is past the bottom of the stack", n)); }
+                words[words.len() - 1 - n as usize] as i64
+            },
+            Expr::Mem(ref e) => {
+                let addr = e.eval(vm)?;
+                if addr < 0 { return Err(format!("invalid address {}", addr)); }
+                vm.memory().read(addr as usize).ok_or(format!("invalid address {:#06X}", addr))? as i64
+            },
+            Expr::Unary(op, ref e) => {
+                let v = e.eval(vm)?;
+                match op {
+                    UnOp::Not    => (v == 0) as i64,
+                    UnOp::Neg    => v.wrapping_neg(),
+                    UnOp::BitNot => !v,
+                }
+            },
+            // short-circuit, so `stack.depth > 0 && stack[0] == 5` is safe
+            Expr::Binary(BinOp::And, ref l, ref r) => (l.eval(vm)? != 0 && r.eval(vm)? != 0) as i64,
+            Expr::Binary(BinOp::Or, ref l, ref r)  => (l.eval(vm)? != 0 || r.eval(vm)? != 0) as i64,
+            Expr::Binary(op, ref l, ref r) => {
+                let (a, b) = (l.eval(vm)?, r.eval(vm)?);
+                match op {
+                    BinOp::BitOr  => a | b,
+                    BinOp::BitXor => a ^ b,
+                    BinOp::BitAnd => a & b,
+                    BinOp::Eq     => (a == b) as i64,
+                    BinOp::Ne     => (a != b) as i64,
+                    BinOp::Lt     => (a < b) as i64,
+                    BinOp::Le     => (a <= b) as i64,
+                    BinOp::Gt     => (a > b) as i64,
+                    BinOp::Ge     => (a >= b) as i64,
+                    BinOp::Add    => a.wrapping_add(b),
+                    BinOp::Sub    => a.wrapping_sub(b),
+                    BinOp::Mul    => a.wrapping_mul(b),
+                    BinOp::Div    => a.checked_div(b).ok_or("division by zero".to_string())?,
+                    BinOp::Rem    => a.checked_rem(b).ok_or("division by zero".to_string())?,
+                    BinOp::And | BinOp::Or => unreachable!(),
+                }
+            },
+        })
+    }
+}
+
+/// How a value in a format string is shown.
+#[derive(Clone,Copy,Debug,PartialEq)]
+enum Style { Decimal, Hex, Char }
+
+#[derive(Clone,Debug,PartialEq)]
+enum Part {
+    Text(String),
+    Value(Expr, Style),
+}
+
+/// Text with `{EXPR}` holes. A hole ending in `:x` shows the value in hex,
+/// `:c` as a character; `{{` and `}}` are literal braces.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Format {
+    parts: Vec<Part>,
+}
+
+pub fn parse_format(src: &str) -> Result<Format,String> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => { chars.next(); text.push('{'); },
+            '}' if chars.peek() == Some(&'}') => { chars.next(); text.push('}'); },
+            '{' => {
+                let mut hole = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => hole.push(c),
+                        None => return Err("'{' without a closing '}'".to_string()),
+                    }
+                }
+                let (src, style) = match hole.rfind(':') {
+                    Some(i) => (&hole[..i], hole[i + 1..].trim()),
+                    None => (&hole[..], ""),
+                };
+                let style = match style {
+                    ""  => Style::Decimal,
+                    "x" => Style::Hex,
+                    "c" => Style::Char,
+                    s   => return Err(format!("unknown style ':{}', expected ':x' or ':c'", s)),
+                };
+                if !text.is_empty() { parts.push(Part::Text(mem::replace(&mut text, String::new()))); }
+                parts.push(Part::Value(parse(src)?, style));
+            },
+            '}' => return Err("'}' without an opening '{'".to_string()),
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() { parts.push(Part::Text(text)); }
+    Ok(Format { parts: parts })
+}
+
+impl Format {
+    /// Fills in the holes from `vm`. A hole that can't be evaluated shows
+    /// the error in angle brackets instead.
+    pub fn render(&self, vm: &Vm) -> String {
+        let mut s = String::new();
+        for part in self.parts.iter() {
+            match *part {
+                Part::Text(ref text) => s.push_str(text),
+                Part::Value(ref e, style) => match (e.eval(vm), style) {
+                    (Ok(v), Style::Decimal) => s.push_str(&v.to_string()),
+                    (Ok(v), Style::Hex)     => s.push_str(&format!("{:#06X}", v)),
+                    (Ok(v), Style::Char)    => s.push(::std::char::from_u32(v as u32).unwrap_or('?')),
+                    (Err(e), _)             => s.push_str(&format!("<{}>", e)),
+                },
+            }
+        }
+        s
+    }
+}
\ No newline at end of file
@@ -0,0 +1,430 @@
+//! A GDB Remote Serial Protocol stub, so gdb (`target remote :PORT`) or
+//! anything else that speaks RSP can drive the VM.
+//!
+//! gdb sees memory as the bytes of the program image: word N lives at byte
+//! address 2N, little-endian, and pc is reported the same way. Registers are
+//! r0-r7 then pc, 16 bits each. Supported packets are `?`, `g`/`G`, `m`/`M`,
+//! `s`, `c`, `Z0`/`z0` breakpoints and `Z2`/`z2`, `Z3`/`z3` watchpoints, plus
+//! the queries gdb needs to connect and fetch the target description.
+//! `c` runs in chunks, checking between them for gdb's interrupt byte.
+
+use std::io::{self,BufReader,Read,Write};
+use std::net::{TcpListener,TcpStream};
+use synacor::{StopReason,WORD};
+use synacor::cpu::NUM_REGISTERS;
+use synacor::memory::MEMORY_SIZE;
+use super::{Breakpoint,Debugger};
+
+/// Instructions `c` runs between checks for an interrupt from gdb.
+const RUN_CHUNK: u64 = 10_000;
+
+const TARGET_XML: &'static str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.synacor.core">
+    <reg name="r0" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="r1" bitsize="16" type="uint16"/>
+    <reg name="r2" bitsize="16" type="uint16"/>
+    <reg name="r3" bitsize="16" type="uint16"/>
+    <reg name="r4" bitsize="16" type="uint16"/>
+    <reg name="r5" bitsize="16" type="uint16"/>
+    <reg name="r6" bitsize="16" type="uint16"/>
+    <reg name="r7" bitsize="16" type="uint16"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// Waits for one connection on localhost:`port` and serves it until gdb
+/// detaches, kills the target or hangs up.
+pub fn serve(debugger: &mut Debugger, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Waiting for gdb on 127.0.0.1:{}", port);
+    let (stream, peer) = listener.accept()?;
+    println!("gdb connected from {}", peer);
+
+    let reader = Box::new(stream.try_clone()?);
+    let socket = Some(stream.try_clone()?);
+    let mut session = Session { debugger: debugger, reader: BufReader::new(reader), writer: Box::new(stream), socket: socket, last: String::new() };
+    session.run()
+}
+
+struct Session<'a> {
+    debugger: &'a mut Debugger,
+    reader: BufReader<Box<dyn Read>>,
+    writer: Box<dyn Write>,
+    socket: Option<TcpStream>, // made non-blocking while checking for an interrupt
+    last: String, // last reply, in case gdb asks for it again
+}
+
+/// What to do after answering a packet.
+enum Reply {
+    Packet(String),
+    Close(String),
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b))
+}
+
+fn hex_bytes(data: &str) -> Option<Vec<u8>> {
+    let digit = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let data = data.as_bytes();
+    if data.len() % 2 != 0 { return None; }
+    data.chunks(2).map(|pair| Some(digit(pair[0])? << 4 | digit(pair[1])?)).collect()
+}
+
+fn word_hex(w: WORD) -> String {
+    format!("{:02x}{:02x}", w & 0xFF, w >> 8)
+}
+
+/// Parses the `addr,length` that several packets start with.
+fn addr_len(args: &str) -> Option<(usize, usize)> {
+    let mut parts = args.splitn(2, ',');
+    let addr = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let len = usize::from_str_radix(parts.next()?, 16).ok()?;
+    Some((addr, len))
+}
+
+impl<'a> Session<'a> {
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            let packet = match self.read_packet()? {
+                Some(p) => p,
+                None => return Ok(()),
+            };
+            match self.handle(&packet) {
+                Reply::Packet(reply) => self.send(reply)?,
+                Reply::Close(reply) => { self.send(reply)?; return Ok(()); },
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut b = [0u8];
+        match self.reader.read(&mut b)? {
+            0 => Ok(None),
+            _ => Ok(Some(b[0])),
+        }
+    }
+
+    /// Reads the next well-formed packet, acknowledging it. Returns None
+    /// when the connection closes.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {},
+                Some(b'-') => { let last = self.last.clone(); self.write_packet(&last)?; continue; },
+                // `+` acks, and a Ctrl-C that arrives while we are stopped anyway
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(b) => data.push(b),
+                }
+            }
+            let mut sum = [0u8; 2];
+            self.reader.read_exact(&mut sum)?;
+
+            let data = String::from_utf8_lossy(&data).into_owned();
+            let expected = ::std::str::from_utf8(&sum).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+            if expected == Some(checksum(&data)) {
+                self.writer.write_all(b"+")?;
+                return Ok(Some(data));
+            }
+            self.writer.write_all(b"-")?;
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        write!(self.writer, "${}#{:02x}", data, checksum(data))?;
+        self.writer.flush()
+    }
+
+    fn send(&mut self, data: String) -> io::Result<()> {
+        self.write_packet(&data)?;
+        self.last = data;
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str) -> Reply {
+        // a packet that doesn't start with an ASCII command has no
+        // arguments we could make sense of
+        let args = packet.get(1..).unwrap_or("");
+        let reply = match packet.bytes().next() {
+            Some(b'?') => "S05".to_string(),
+            Some(b'g') => self.read_registers(),
+            Some(b'G') => self.write_registers(args),
+            Some(b'm') => self.read_memory(args),
+            Some(b'M') => self.write_memory(args),
+            Some(b's') => { let reason = self.debugger.resume(Some(1)); self.stop_reply(reason) },
+            Some(b'c') => { let reason = self.resume(); self.stop_reply(reason) },
+            Some(b'Z') => self.breakpoint(true, args),
+            Some(b'z') => self.breakpoint(false, args),
+            Some(b'H') => "OK".to_string(),
+            Some(b'q') => self.query(args),
+            Some(b'D') => return Reply::Close("OK".to_string()),
+            Some(b'k') => return Reply::Close(String::new()),
+            _          => String::new(),
+        };
+        Reply::Packet(reply)
+    }
+
+    /// Runs until the program stops by itself or gdb interrupts it.
+    fn resume(&mut self) -> StopReason {
+        loop {
+            let reason = self.debugger.resume(Some(RUN_CHUNK));
+            if reason != StopReason::BudgetExhausted { return reason; }
+            match self.interrupt_requested() {
+                Ok(false) => {},
+                _ => return StopReason::Interrupted,
+            }
+        }
+    }
+
+    /// Whether gdb has sent its interrupt byte (0x03) or hung up. Anything
+    /// else sent while the program runs is dropped.
+    fn interrupt_requested(&mut self) -> io::Result<bool> {
+        if let Some(ref s) = self.socket { s.set_nonblocking(true)?; }
+        let mut b = [0u8];
+        let read = self.reader.read(&mut b);
+        if let Some(ref s) = self.socket { s.set_nonblocking(false)?; }
+        match read {
+            Ok(0) => Ok(true),
+            Ok(_) => Ok(b[0] == 0x03),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn query(&self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            "PacketSize=4000;qXfer:features:read+;swbreak+".to_string()
+        } else if args.starts_with("Xfer:features:read:target.xml:") {
+            match addr_len(&args["Xfer:features:read:target.xml:".len()..]) {
+                Some((off, len)) => {
+                    let off = off.min(TARGET_XML.len());
+                    let end = off.saturating_add(len).min(TARGET_XML.len());
+                    format!("{}{}", if end == TARGET_XML.len() { "l" } else { "m" }, &TARGET_XML[off..end])
+                },
+                None => "E01".to_string(),
+            }
+        } else if args == "Attached" {
+            "1".to_string()
+        } else if args == "fThreadInfo" {
+            "m1".to_string()
+        } else if args == "sThreadInfo" {
+            "l".to_string()
+        } else if args == "C" {
+            "QC1".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        let cpu = self.debugger.vm().cpu();
+        let mut reply = cpu.registers().iter().map(|&r| word_hex(r)).collect::<String>();
+        reply.push_str(&word_hex((cpu.pc() * 2) as WORD));
+        reply
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let bytes = match hex_bytes(args) {
+            Some(ref b) if b.len() == (NUM_REGISTERS + 1) * 2 => b.clone(),
+            _ => return "E01".to_string(),
+        };
+        let words = bytes.chunks(2).map(|c| c[0] as WORD | (c[1] as WORD) << 8).collect::<Vec<_>>();
+        let vm = self.debugger.vm_mut();
+        for r in 0..NUM_REGISTERS {
+            vm.set_register(r, words[r]);
+        }
+        vm.set_pc(words[NUM_REGISTERS] as usize / 2);
+        "OK".to_string()
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let (addr, len) = match addr_len(args) {
+            Some(al) => al,
+            None => return "E01".to_string(),
+        };
+        if addr >= MEMORY_SIZE * 2 { return "E14".to_string(); }
+        let memory = self.debugger.vm().memory();
+        let mut reply = String::new();
+        for byte in addr..addr.saturating_add(len).min(MEMORY_SIZE * 2) {
+            let w = memory.read(byte / 2).unwrap_or(0);
+            reply.push_str(&format!("{:02x}", if byte % 2 == 0 { w & 0xFF } else { w >> 8 }));
+        }
+        reply
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let mut parts = args.splitn(2, ':');
+        let (addr, len) = match parts.next().and_then(addr_len) {
+            Some(al) => al,
+            None => return "E01".to_string(),
+        };
+        let bytes = match parts.next().and_then(hex_bytes) {
+            Some(ref b) if b.len() == len => b.clone(),
+            _ => return "E01".to_string(),
+        };
+        if addr.checked_add(len).map_or(true, |end| end > MEMORY_SIZE * 2) { return "E14".to_string(); }
+
+        let vm = self.debugger.vm_mut();
+        for (i, &b) in bytes.iter().enumerate() {
+            let byte = addr + i;
+            let w = vm.memory().read(byte / 2).unwrap_or(0);
+            let w = if byte % 2 == 0 { (w & 0xFF00) | b as WORD } else { (w & 0x00FF) | (b as WORD) << 8 };
+            vm.write_memory(byte / 2, w);
+        }
+        "OK".to_string()
+    }
+
+    /// `Z`/`z` TYPE,ADDR,KIND: inserts or removes a breakpoint or watchpoint.
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut parts = args.splitn(2, ',');
+        let kind = parts.next().unwrap_or("");
+        let (addr, len) = match parts.next().and_then(addr_len) {
+            Some(al) => al,
+            None => return "E01".to_string(),
+        };
+        // a watchpoint covers whole words, and has to stay inside memory
+        let last = match addr.checked_add(len.max(1) - 1) {
+            Some(last) if last < MEMORY_SIZE * 2 => last,
+            _ => return "E01".to_string(),
+        };
+        let (start, end) = (addr / 2, last / 2);
+        let bp = match kind {
+            "0" => Breakpoint::Instruction((addr / 2) as u16),
+            "2" => Breakpoint::MemoryWrite(start, end),
+            "3" => Breakpoint::MemoryRead(start, end),
+            _   => return String::new(),
+        };
+        if insert {
+            self.debugger.add_entry(bp, None);
+        } else if let Some(i) = self.debugger.entries.iter().position(|e| e.breakpoint == bp) {
+            self.debugger.entries.remove(i);
+        }
+        "OK".to_string()
+    }
+
+    fn stop_reply(&self, reason: StopReason) -> String {
+        match reason {
+            StopReason::Halted => "W00".to_string(),
+            StopReason::Error(e) => {
+                println!("VM error: {}", e);
+                "S04".to_string()
+            },
+            StopReason::BreakpointHit => {
+                let watched = self.debugger.hits.iter()
+                    .filter_map(|h| self.debugger.entries.iter().find(|e| e.id == h.id))
+                    .filter_map(|e| match e.breakpoint {
+                        Breakpoint::MemoryWrite(a, _) => Some(("watch", a)),
+                        Breakpoint::MemoryRead(a, _)  => Some(("rwatch", a)),
+                        _ => None,
+                    })
+                    .next();
+                match watched {
+                    Some((kind, a)) => format!("T05{}:{:x};", kind, a * 2),
+                    None => "T05swbreak:;".to_string(),
+                }
+            },
+            StopReason::Interrupted => "S02".to_string(),
+            StopReason::BudgetExhausted | StopReason::WaitingForInput => "S05".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self,BufReader,Cursor};
+    use synacor;
+    use debugger::Debugger;
+    use super::{Reply,Session,RUN_CHUNK,addr_len,checksum,hex_bytes};
+
+    fn session<'a>(debugger: &'a mut Debugger, input: &[u8]) -> Session<'a> {
+        Session { debugger: debugger, reader: BufReader::new(Box::new(Cursor::new(input.to_vec()))), writer: Box::new(io::sink()), socket: None, last: String::new() }
+    }
+
+    fn reply(session: &mut Session, packet: &str) -> String {
+        match session.handle(packet) {
+            Reply::Packet(r) | Reply::Close(r) => r,
+        }
+    }
+
+    #[test]
+    fn test_packet_helpers() {
+        assert_eq!(0, checksum(""));
+        assert_eq!(0x67, checksum("g"));
+        assert_eq!(0x9A, checksum("OK"));
+
+        assert_eq!(Some(vec![0x12, 0xAB]), hex_bytes("12aB"));
+        assert_eq!(Some(vec![]), hex_bytes(""));
+        assert_eq!(None, hex_bytes("123"));
+        assert_eq!(None, hex_bytes("+1"));
+        assert_eq!(None, hex_bytes("\u{FFFD}0"));
+
+        assert_eq!(Some((0x10, 4)), addr_len("10,4"));
+        assert_eq!(None, addr_len("10"));
+        assert_eq!(None, addr_len("x,4"));
+        assert_eq!(None, addr_len("10,4,"));
+    }
+
+    #[test]
+    fn test_session() {
+        // set r0 0x1234; halt
+        let mut vm = synacor::Vm::with_console(Box::new(synacor::BufferConsole::new()));
+        vm.load_memory(vec![1, 32768, 0x1234, 0]).unwrap();
+        let mut debugger = Debugger::new(vm);
+
+        // a packet with a bad checksum is skipped
+        let mut s = session(&mut debugger, b"$?#00$g#67");
+        assert_eq!(Some("g".to_string()), s.read_packet().unwrap());
+        assert_eq!(None, s.read_packet().unwrap());
+
+        assert_eq!("0000".repeat(9), reply(&mut s, "g"));
+        let registers = format!("00000700{}0400", "0000".repeat(6));
+        assert_eq!("OK", reply(&mut s, &format!("G{}", registers)));
+        assert_eq!(registers, reply(&mut s, "g"));
+        assert_eq!("E01", reply(&mut s, "G0000"));
+        assert_eq!("OK", reply(&mut s, &format!("G{}", "0000".repeat(9))));
+
+        assert_eq!("0100008034120000", reply(&mut s, "m0,8"));
+        assert_eq!("0000", reply(&mut s, "m1fffe,ffffffffffffffff"));
+        assert_eq!("E14", reply(&mut s, "m20000,2"));
+        assert_eq!("OK", reply(&mut s, "M9,1:2a"));
+        assert_eq!("002a0000", reply(&mut s, "m8,4"));
+        assert_eq!("E14", reply(&mut s, "Mffffffffffffffff,2:0000"));
+        assert_eq!("E01", reply(&mut s, "M0,2:00"));
+
+        assert_eq!("OK", reply(&mut s, "Z0,6,2"));
+        assert_eq!("OK", reply(&mut s, "Z2,4,3"));
+        assert_eq!("E01", reply(&mut s, "Z2,0,ffffffff"));
+        assert_eq!(2, s.debugger.entries.len());
+        assert_eq!("T05swbreak:;", reply(&mut s, "c"));
+        assert_eq!("OK", reply(&mut s, "z0,6,2"));
+        assert_eq!("OK", reply(&mut s, "z2,4,3"));
+        assert!(s.debugger.entries.is_empty());
+
+        assert_eq!("", reply(&mut s, "\u{FFFD}abc"));
+        assert_eq!("", reply(&mut s, ""));
+        assert_eq!("W00", reply(&mut s, "c"));
+    }
+
+    #[test]
+    fn test_interrupt() {
+        // jmp 0
+        let mut vm = synacor::Vm::with_console(Box::new(synacor::BufferConsole::new()));
+        vm.load_memory(vec![6, 0]).unwrap();
+        let mut debugger = Debugger::new(vm);
+
+        let mut s = session(&mut debugger, b"+\x03");
+        assert_eq!("S02", reply(&mut s, "c"));
+        assert!(s.debugger.vm().instruction_count() >= 2 * RUN_CHUNK);
+    }
+}
\ No newline at end of file
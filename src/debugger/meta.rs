@@ -0,0 +1,62 @@
+//! Meta-commands: lines typed at the game's prompt that start with the
+//! meta prefix (`!` unless `--meta-prefix` says otherwise) go to the host
+//! instead of the program.
+//!
+//! ```text
+//! !save slot1            save the machine state to slot1
+//! !set r7 25734          anything the debugger's set takes
+//! !trace on [FILE]       start a JSON Lines trace, to trace.jsonl by default
+//! !trace off
+//! !dbg                   stop in the debugger
+//! ```
+
+use synacor::{Tracer,Vm};
+use super::assign;
+
+const DEFAULT_TRACE_FILE: &'static str = "trace.jsonl";
+
+const HELP: &'static str = "\
+save FILE             save the machine state to FILE
+set rN|pc|[ADDR] EXPR change a register, pc or memory word
+trace on [FILE]       write a JSON Lines trace to FILE (default trace.jsonl)
+trace off             stop tracing
+dbg                   stop in the debugger
+";
+
+/// What to do once a meta-command has run.
+#[derive(Debug,PartialEq)]
+pub enum Action {
+    Resume,
+    Debug,
+}
+
+/// Runs one meta-command against `vm`, printing anything it has to say.
+pub fn execute(vm: &mut Vm, line: &str) -> Action {
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    let rest = line.trim()[words.get(0).map_or(0, |w| w.len())..].trim();
+    match words.get(0).cloned().unwrap_or("") {
+        "save" => match words.get(1) {
+            Some(file) => match vm.snapshot().save(file) {
+                Ok(_) => println!("Saved machine state to {}", file),
+                Err(e) => println!("Unable to save snapshot {}: {}", file, e),
+            },
+            None => println!("Usage: save FILE"),
+        },
+        "set" => if let Err(e) = assign(vm, rest) { println!("{}", e); },
+        "trace" => match (words.get(1).cloned(), words.get(2).cloned()) {
+            (Some("on"), file) => {
+                let file = file.unwrap_or(DEFAULT_TRACE_FILE);
+                match Tracer::to_file(file) {
+                    Ok(tracer) => { vm.set_tracer(Some(tracer)); println!("Tracing to {}", file); },
+                    Err(e) => println!("Unable to create trace file {}: {}", file, e),
+                }
+            },
+            (Some("off"), None) => { vm.set_tracer(None); println!("Tracing stopped"); },
+            _ => println!("Usage: trace on [FILE] | trace off"),
+        },
+        "dbg" => return Action::Debug,
+        "help" | "" => print!("{}", HELP),
+        w => println!("Unknown meta-command '{}'", w),
+    }
+    Action::Resume
+}
\ No newline at end of file
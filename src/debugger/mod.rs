@@ -1,11 +1,1234 @@
-enum Breakpoint {
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self,File};
+use std::io::{self,Write};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use synacor::{parse_number,Event,LineTable,MetaCommands,StopReason,Symbols,Vm,WORD};
+use synacor::cpu::NUM_REGISTERS;
+use synacor::effect::{Effect,Read};
+use synacor::memory::MEMORY_SIZE;
+use synacor::opcode::Opcode;
+
+pub mod dap;
+pub mod disasm;
+pub mod expr;
+pub mod gdb;
+pub mod meta;
+pub mod search;
+pub mod tui;
+
+use self::expr::{Expr,Format};
+
+/// How many instructions `rstep` can go back over.
+const UNDO_DEPTH: usize = 100_000;
+
+/// Commands read from the current directory whenever the debugger starts.
+pub const RC_FILE: &'static str = ".synacordbgrc";
+
+/// How deeply user commands and command files may call each other.
+const MAX_NESTING: usize = 32;
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Breakpoint {
     Instruction(u16),
     RegisterRead(usize),
     RegisterWrite(usize),
-    MemoryRead(usize),
-    MemoryWrite(usize)
+    /// The first and last words watched.
+    MemoryRead(usize, usize),
+    MemoryWrite(usize, usize)
+}
+
+impl Breakpoint {
+    fn is_read(&self) -> bool {
+        match *self {
+            Breakpoint::RegisterRead(_) | Breakpoint::MemoryRead(..) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Breakpoint::Instruction(a)    => write!(f, "break at {:#06X}", a),
+            Breakpoint::RegisterRead(r)   => write!(f, "read of r{}", r),
+            Breakpoint::RegisterWrite(r)  => write!(f, "write to r{}", r),
+            Breakpoint::MemoryRead(a, e)  => write!(f, "read of {}", words(a, e)),
+            Breakpoint::MemoryWrite(a, e) => write!(f, "write to {}", words(a, e)),
+        }
+    }
+}
+
+fn words(start: usize, end: usize) -> String {
+    if start == end { format!("[{:#06X}]", start) } else { format!("[{:#06X}..{:#06X}]", start, end) }
+}
+
+/// What the REPL should do after a command.
+#[derive(Debug,PartialEq)]
+pub enum Control {
+    Continue,
+    Quit,
+}
+
+/// A breakpoint or watchpoint, and what decides whether passing it stops.
+struct Entry {
+    id: usize,
+    breakpoint: Breakpoint,
+    condition: Option<(String, Expr)>,
+    ignore: u64, // passes still to skip
+    hits: u64,   // passes where the condition held
+    commands: Vec<String>, // run when this breakpoint stops the program
+    trace: Option<(String, Format)>, // print this instead of stopping
+}
+
+/// Where the lines of a `define` or `commands` block go once `end` is seen.
+enum BlockTarget {
+    Define(String),
+    Commands(usize),
+}
+
+/// A `define` or `commands` block still being read.
+struct Block {
+    target: BlockTarget,
+    lines: Vec<String>,
+    nested: usize, // blocks opened inside this one, which need their own `end`
 }
 
 pub struct Debugger {
-    
+    vm: Vm,
+    entries: Vec<Entry>,
+    displays: Vec<(usize, String, Expr)>,
+    next_id: usize,
+    next_display: usize,
+    last_command: String,
+    hits: Vec<WatchHit>, // watchpoints that fired during the last run
+    stopped_at: Vec<usize>, // instruction breakpoints that stopped the last run
+    notes: Vec<String>, // problems evaluating conditions during the last run
+    defines: HashMap<String, Vec<String>>,
+    block: Option<Block>,
+    stops: u64, // runs so far, to tell when a command resumed the program
+    last_stop: Option<StopReason>, // why the last reported run stopped
+    in_commands: bool,
+    nesting: usize,
+    trace_file: Option<File>,
+    echo_traces: bool, // print trace messages as they happen, not at the next stop
+    meta: Option<MetaCommands>,
+    sources: HashMap<String, Vec<String>>, // the text of the line table's files
+}
+
+/// A watchpoint that fired, and the instruction that set it off.
+struct WatchHit {
+    id: usize,
+    pc: usize,
+    addr: Option<usize>, // the word, for memory watchpoints
+    old: Option<WORD>, // None for reads
+    value: WORD,
+}
+
+fn parse_register(s: &str) -> Option<usize> {
+    if s.len() == 2 && s.starts_with('r') {
+        s[1..].parse::<usize>().ok().and_then(|r| if r < NUM_REGISTERS { Some(r) } else { None })
+    } else {
+        None
+    }
+}
+
+/// Collects the watchpoints set off by one instruction's writes and reads.
+fn watch_hits(entries: &[Entry], pc: usize, effects: &[Effect], reads: &[Read], hits: &mut Vec<WatchHit>) {
+    for &Entry { id, breakpoint: bp, .. } in entries.iter() {
+        for effect in effects.iter() {
+            match (bp, *effect) {
+                (Breakpoint::RegisterWrite(r), Effect::RegisterWrite { reg, old, new }) if r == reg =>
+                    hits.push(WatchHit { id: id, pc: pc, addr: None, old: Some(old), value: new }),
+                (Breakpoint::MemoryWrite(a, e), Effect::MemoryWrite { addr, old, new }) if a <= addr && addr <= e =>
+                    hits.push(WatchHit { id: id, pc: pc, addr: Some(addr), old: Some(old), value: new }),
+                _ => {},
+            }
+        }
+        for read in reads.iter() {
+            match (bp, *read) {
+                (Breakpoint::RegisterRead(r), Read::Register { reg, value }) if r == reg =>
+                    hits.push(WatchHit { id: id, pc: pc, addr: None, old: None, value: value }),
+                (Breakpoint::MemoryRead(a, e), Read::Memory { addr, value }) if a <= addr && addr <= e =>
+                    hits.push(WatchHit { id: id, pc: pc, addr: Some(addr), old: None, value: value }),
+                _ => {},
+            }
+        }
+    }
+}
+
+/// Splits `ARGS if EXPR` into the arguments and the parsed condition.
+fn split_condition(rest: &str) -> Result<(&str, Option<(String, Expr)>),String> {
+    match rest.find(" if ") {
+        Some(i) => {
+            let src = rest[i + 4..].trim();
+            Ok((&rest[..i], Some((src.to_string(), expr::parse(src)?))))
+        },
+        None => Ok((rest, None)),
+    }
+}
+
+/// `rN|pc|[ADDR] [=] EXPR`: stores the value of EXPR, as `set` does.
+pub fn assign(vm: &mut Vm, rest: &str) -> Result<(),String> {
+    let split = if rest.starts_with('[') {
+        rest.find(']').map(|i| i + 1)
+    } else {
+        rest.find(|c: char| c.is_whitespace() || c == '=')
+    };
+    let (target, value) = match split {
+        Some(i) => (&rest[..i], rest[i..].trim().trim_start_matches('=').trim()),
+        None => return Err("Usage: set rN|pc|[ADDR] EXPR".to_string()),
+    };
+    let value = match expr::parse(value).and_then(|e| e.eval(vm)) {
+        Ok(v) if v >= 0 && v <= 0xFFFF => v as WORD,
+        Ok(v) => return Err(format!("Error: {} doesn't fit in a word", v)),
+        Err(e) => return Err(format!("Error: {}", e)),
+    };
+
+    if target == "pc" {
+        vm.set_pc(value as usize);
+    } else if let Some(r) = parse_register(target) {
+        vm.set_register(r, value);
+    } else if target.starts_with('[') {
+        match expr::parse(&target[1..target.len() - 1]).and_then(|e| e.eval(vm)) {
+            Ok(addr) if addr >= 0 && vm.write_memory(addr as usize, value) => {},
+            Ok(addr) => return Err(format!("Error: invalid address {:#06X}", addr)),
+            Err(e) => return Err(format!("Error: {}", e)),
+        }
+    } else {
+        return Err(format!("Can't set '{}'", target));
+    }
+    Ok(())
+}
+
+fn format_value(v: i64) -> String {
+    if v >= 0 && v <= 0xFFFF { format!("{} ({:#06X})", v, v) } else { format!("{}", v) }
+}
+
+const HELP: &'static str = "\
+break ADDR      (b)   stop before executing ADDR
+watch rN|ADDR   (w)   stop after a write to a register or memory word
+rwatch rN|ADDR  (rw)  stop after a read of a register or memory word
+                      an ADDR followed by END watches every word in ADDR..END
+                      any of these can end with `if EXPR` to stop only when
+                      EXPR is non-zero, e.g. `b 0x178B if r7 != 0`
+                      with symbols loaded, an ADDR can be a label or
+                      LABEL+N, e.g. `b print_word+3`, and with a line table
+                      FILE:LINE, e.g. `b hello.asm:42`
+trace ADDR \"FMT\"      print FMT whenever ADDR runs, without stopping; {EXPR}
+                      in FMT shows EXPR, {EXPR:x} in hex, {EXPR:c} as a char
+tracelog [FILE]       write trace messages to FILE, or back to the console
+cond N [EXPR]         set or clear the condition on breakpoint N
+ignore N COUNT        skip the next COUNT stops at breakpoint N
+breakpoints     (bl)  list breakpoints and watchpoints
+delete N        (d)   delete breakpoint or watchpoint N
+print EXPR      (p)   evaluate EXPR, e.g. `p [0x0AAC] + stack.depth`
+display [EXPR]        show EXPR at every stop; alone, show them all now
+undisplay N           stop showing display N
+step [N]        (s)   execute N source lines, or instructions if there is no
+                      line table (default 1)
+next [N]        (n)   step, running calls to completion
+stepi [N]       (si)  execute N instructions (default 1)
+nexti           (ni)  step an instruction, running a call to completion
+finish          (fin) run until the current call returns
+continue        (c)   run until a breakpoint, halt or error
+rstep [N]       (rs)  undo the last N instructions (default 1)
+regs            (r)   show pc and registers
+stack                 show the data stack, top first
+bt                    show the call backtrace
+x/N ADDR              dump N words of memory starting at ADDR
+disas [ADDR] [N]      disassemble N instructions around pc or from ADDR
+sym LABEL[+N]|ADDR    show the address of a label, or the label of an address
+symbol-file FILE      load labels from a .sym file
+line-file FILE        load a line table from a .lines file
+who-wrote rN|ADDR     show which instruction last wrote a register or word
+track [ADDR [END]]    keep every write to ADDR..END for who-wrote, not just
+                      the last; alone, list the ranges kept
+track on|off          start or stop recording writes for who-wrote (off by
+                      default)
+find \"TEXT\"           list where TEXT is stored, one character per word
+find WORD|? ...       list where a run of words is stored; ? matches any word
+find pstr \"TEXT\"      list length-prefixed strings containing TEXT
+set rN|pc|[ADDR] EXPR change a register, pc or memory word
+echo TEXT             print TEXT
+define NAME           make a command of the lines up to `end`; $arg0.. and
+                      $argc are replaced by the arguments it is given
+commands [N]          run the lines up to `end` whenever breakpoint N (or
+                      the last one set) stops the program
+source FILE           run the commands in FILE
+quit            (q)   leave the debugger
+An empty line repeats the last command.
+";
+
+impl Debugger {
+    pub fn new(mut vm: Vm) -> Debugger {
+        vm.set_undo_depth(UNDO_DEPTH);
+        let mut debugger = Debugger {
+            vm: vm,
+            entries: Vec::new(),
+            displays: Vec::new(),
+            next_id: 1,
+            next_display: 1,
+            last_command: String::new(),
+            hits: Vec::new(),
+            stopped_at: Vec::new(),
+            notes: Vec::new(),
+            defines: HashMap::new(),
+            block: None,
+            stops: 0,
+            last_stop: None,
+            in_commands: false,
+            nesting: 0,
+            trace_file: None,
+            echo_traces: false,
+            meta: None,
+            sources: HashMap::new(),
+        };
+        debugger.load_sources();
+        debugger
+    }
+
+    /// Reads the files named in the line table, so stops can show the line.
+    fn load_sources(&mut self) {
+        self.sources = self.vm.line_table().files().iter()
+            .filter_map(|f| fs::read_to_string(f).ok().map(|text| (f.clone(), text.lines().map(|l| l.to_string()).collect())))
+            .collect();
+    }
+
+    /// Has meta-commands typed into the program's input run while the
+    /// debugger is running it.
+    pub fn set_meta_commands(&mut self, meta: MetaCommands) { self.meta = Some(meta); }
+
+    pub fn vm(&self) -> &Vm { &self.vm }
+    pub fn vm_mut(&mut self) -> &mut Vm { &mut self.vm }
+    pub fn into_vm(self) -> Vm { self.vm }
+
+    /// Reads commands from stdin until `quit` or end of input.
+    pub fn repl(&mut self) {
+        self.echo_traces = true;
+        let stdout = io::stdout();
+        {
+            let mut out = stdout.lock();
+            let _ = self.show_location(&mut out);
+        }
+        loop {
+            print!("{}", if self.block.is_some() { "> " } else { "(synacor) " });
+            let _ = io::stdout().flush();
+
+            let mut line = String::new();
+            match io::stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {},
+            }
+
+            let mut out = stdout.lock();
+            match self.command(&line, &mut out) {
+                Ok(Control::Quit) => break,
+                Ok(Control::Continue) => {},
+                Err(e) => { let _ = writeln!(out, "Error writing output: {}", e); break; },
+            }
+        }
+    }
+
+    /// Runs one command line, writing anything it has to say to `out`.
+    pub fn command(&mut self, line: &str, out: &mut dyn Write) -> io::Result<Control> {
+        if self.block.is_some() {
+            self.block_line(line.trim());
+            return Ok(Control::Continue);
+        }
+
+        let mut line = line.trim().to_string();
+        if line.is_empty() { line = self.last_command.clone(); }
+        if line.is_empty() { return Ok(Control::Continue); }
+
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let args = &words[1..];
+        let rest = line[words[0].len()..].trim();
+        let stops = self.stops;
+        match words[0] {
+            "help" | "h" | "?"      => write!(out, "{}", HELP)?,
+            "break" | "b"           => self.cmd_break(rest, out)?,
+            "trace"                 => self.cmd_trace(rest, out)?,
+            "tracelog"              => self.cmd_tracelog(rest, out)?,
+            "watch" | "w"           => self.cmd_watch(false, rest, out)?,
+            "rwatch" | "rw"         => self.cmd_watch(true, rest, out)?,
+            "cond"                  => self.cmd_cond(rest, out)?,
+            "ignore"                => self.cmd_ignore(args, out)?,
+            "breakpoints" | "bl"    => self.cmd_breakpoints(out)?,
+            "delete" | "d"          => self.cmd_delete(args, out)?,
+            "print" | "p"           => match expr::parse(rest) {
+                Ok(e) => match e.eval(&self.vm) {
+                    Ok(v) => writeln!(out, "{}", format_value(v))?,
+                    Err(e) => writeln!(out, "Error: {}", e)?,
+                },
+                Err(e) => writeln!(out, "Error: {}", e)?,
+            },
+            "display"               => self.cmd_display(rest, out)?,
+            "undisplay"             => self.cmd_undisplay(args, out)?,
+            "step" | "s"            => {
+                let n = args.get(0).and_then(|a| parse_number(a)).unwrap_or(1);
+                let reason = if self.vm.line_table().is_empty() { self.resume(Some(n as u64)) } else { self.step_lines(n as u64, false) };
+                self.report(reason, out)?;
+            },
+            "next" | "n"            => {
+                let n = args.get(0).and_then(|a| parse_number(a)).unwrap_or(1);
+                let reason = if self.vm.line_table().is_empty() { self.next_instructions(n as u64) } else { self.step_lines(n as u64, true) };
+                self.report(reason, out)?;
+            },
+            "stepi" | "si"          => {
+                let n = args.get(0).and_then(|a| parse_number(a)).unwrap_or(1);
+                let reason = self.resume(Some(n as u64));
+                self.report(reason, out)?;
+            },
+            "nexti" | "ni"          => {
+                let reason = self.next();
+                self.report(reason, out)?;
+            },
+            "finish" | "fin"        => match self.vm.cpu().call_stack().frames().last().cloned() {
+                Some(frame) => {
+                    writeln!(out, "Run till exit from {:#06X}", frame.callee)?;
+                    let reason = self.finish();
+                    self.report(reason, out)?;
+                },
+                None => writeln!(out, "Not inside a call")?,
+            },
+            "continue" | "c"        => {
+                let reason = self.resume(None);
+                self.report(reason, out)?;
+            },
+            "rstep" | "rs"          => {
+                let n = args.get(0).and_then(|a| parse_number(a)).unwrap_or(1);
+                let undone = self.vm.step_back(n);
+                if undone < n { writeln!(out, "Only {} instructions could be undone", undone)?; }
+                self.show_location(out)?;
+            },
+            "regs" | "r"            => self.cmd_regs(out)?,
+            "stack"                 => self.cmd_stack(out)?,
+            "bt"                    => self.cmd_backtrace(out)?,
+            "disas" | "dis"         => self.cmd_disas(args, out)?,
+            "sym"                   => self.cmd_sym(args, out)?,
+            "symbol-file"           => self.cmd_symbol_file(args, out)?,
+            "line-file"             => self.cmd_line_file(args, out)?,
+            "who-wrote"             => self.cmd_who_wrote(args, out)?,
+            "track"                 => self.cmd_track(args, out)?,
+            "find"                  => match search::parse(rest) {
+                Ok(pattern) => search::report(self.vm.memory().words(), &pattern, out)?,
+                Err(e) => writeln!(out, "{}", e)?,
+            },
+            "set"                   => self.cmd_set(rest, out)?,
+            "echo"                  => writeln!(out, "{}", rest)?,
+            "define"                => self.cmd_define(args, out)?,
+            "commands"              => self.cmd_commands(args, out)?,
+            "source"                => if self.source(rest, out)? == Control::Quit { return Ok(Control::Quit); },
+            "quit" | "q"            => return Ok(Control::Quit),
+            w if self.defines.contains_key(w) => if self.run_define(w, args, out)? == Control::Quit { return Ok(Control::Quit); },
+            w if w == "x" || w.starts_with("x/") => self.cmd_examine(w, args, out)?,
+            w                       => writeln!(out, "Unknown command '{}', try 'help'", w)?,
+        }
+        self.last_command = line.clone();
+
+        if self.stops != stops { self.breakpoint_commands(out) } else { Ok(Control::Continue) }
+    }
+
+    /// Runs the commands in the file at `path`, one per line. Blank lines and
+    /// lines starting with `#` are skipped.
+    pub fn source<P: AsRef<Path>>(&mut self, path: P, out: &mut dyn Write) -> io::Result<Control> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => { writeln!(out, "Unable to read {}: {}", path.display(), e)?; return Ok(Control::Continue); },
+        };
+        if self.nesting >= MAX_NESTING { writeln!(out, "Too many nested command files")?; return Ok(Control::Continue); }
+
+        self.nesting += 1;
+        let mut control = Ok(Control::Continue);
+        for line in text.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            control = self.command(line, out);
+            match control {
+                Ok(Control::Continue) => {},
+                _ => break,
+            }
+        }
+        self.nesting -= 1;
+
+        if self.block.take().is_some() { writeln!(out, "Missing 'end' in {}", path.display())?; }
+        control
+    }
+
+    /* SCRIPTING */
+
+    /// Adds a line to the block being read, closing it at the matching `end`.
+    fn block_line(&mut self, line: &str) {
+        if line.is_empty() || line.starts_with('#') { return; }
+        let done = {
+            let block = self.block.as_mut().unwrap();
+            let first = line.split_whitespace().next().unwrap_or("");
+            if first == "end" && block.nested == 0 {
+                true
+            } else {
+                if first == "end" { block.nested -= 1; }
+                if first == "define" || first == "commands" { block.nested += 1; }
+                block.lines.push(line.to_string());
+                false
+            }
+        };
+        if !done { return; }
+
+        let block = self.block.take().unwrap();
+        match block.target {
+            BlockTarget::Define(name) => { self.defines.insert(name, block.lines); },
+            BlockTarget::Commands(id) => if let Some(entry) = self.entries.iter_mut().find(|e| e.id == id) {
+                entry.commands = block.lines;
+            },
+        }
+    }
+
+    fn cmd_define(&mut self, args: &[&str], out: &mut dyn Write) -> io::Result<()> {
+        match args.get(0) {
+            Some(name) => {
+                self.block = Some(Block { target: BlockTarget::Define(name.to_string()), lines: Vec::new(), nested: 0 });
+                Ok(())
+            },
+            None => writeln!(out, "Usage: define NAME"),
+        }
+    }
+
+    fn cmd_commands(&mut self, args: &[&str], out: &mut dyn Write) -> io::Result<()> {
+        let id = match args.get(0) {
+            Some(a) => parse_number(a),
+            None => self.entries.last().map(|e| e.id),
+        };
+        match id {
+            Some(id) if self.entries.iter().any(|e| e.id == id) => {
+                self.block = Some(Block { target: BlockTarget::Commands(id), lines: Vec::new(), nested: 0 });
+                Ok(())
+            },
+            Some(id) => writeln!(out, "No breakpoint {}", id),
+            None => writeln!(out, "Usage: commands [N]"),
+        }
+    }
+
+    /// Runs a user command, replacing `$argc` and `$arg0`.. in its lines.
+    fn run_define(&mut self, name: &str, args: &[&str], out: &mut dyn Write) -> io::Result<Control> {
+        if self.nesting >= MAX_NESTING { writeln!(out, "Too many nested user commands")?; return Ok(Control::Continue); }
+
+        let lines = self.defines[name].clone();
+        self.nesting += 1;
+        let mut control = Ok(Control::Continue);
+        for line in lines {
+            // highest first, so $arg1 doesn't eat the start of $arg10
+            let mut line = line.replace("$argc", &args.len().to_string());
+            for (i, arg) in args.iter().enumerate().rev() {
+                line = line.replace(&format!("$arg{}", i), arg);
+            }
+            control = self.command(&line, out);
+            match control {
+                Ok(Control::Continue) => {},
+                _ => break,
+            }
+        }
+        self.nesting -= 1;
+        control
+    }
+
+    /// Runs the `commands` of the breakpoints the program stopped at. If one
+    /// resumes the program, the rest of the list is dropped and the commands
+    /// for wherever it stops next run instead, as gdb does.
+    fn breakpoint_commands(&mut self, out: &mut dyn Write) -> io::Result<Control> {
+        // a resume inside the list is picked up by the loop below
+        if self.in_commands { return Ok(Control::Continue); }
+
+        self.in_commands = true;
+        let mut control = Ok(Control::Continue);
+        'stops: loop {
+            let mut ids = self.stopped_at.clone();
+            ids.extend(self.hits.iter().map(|h| h.id));
+            ids.dedup();
+            let lines = ids.iter()
+                .filter_map(|id| self.entries.iter().find(|e| e.id == *id))
+                .flat_map(|e| e.commands.clone())
+                .collect::<Vec<_>>();
+
+            let stops = self.stops;
+            for line in lines {
+                control = self.command(&line, out);
+                match control {
+                    Ok(Control::Continue) => {},
+                    _ => break 'stops,
+                }
+                if self.stops != stops { continue 'stops; }
+            }
+            break;
+        }
+        self.in_commands = false;
+        control
+    }
+
+    /// `set rN|pc|[ADDR] [=] EXPR`
+    fn cmd_set(&mut self, rest: &str, out: &mut dyn Write) -> io::Result<()> {
+        match assign(&mut self.vm, rest) {
+            Ok(()) => Ok(()),
+            Err(e) => writeln!(out, "{}", e),
+        }
+    }
+
+    /* RUNNING */
+
+    /// Runs until `stop` says so, a breakpoint or watchpoint whose
+    /// condition holds fires, or `budget` instructions have executed.
+    fn run<F: FnMut(&Event) -> bool>(&mut self, budget: Option<u64>, mut stop: F) -> StopReason {
+        self.vm.set_record_reads(self.entries.iter().any(|e| e.breakpoint.is_read()));
+        self.hits.clear();
+        self.stopped_at.clear();
+        self.notes.clear();
+        self.stops += 1;
+        // a Ctrl-C typed at the prompt shouldn't cut short the next command
+        self.vm.interrupt_handle().store(false, Ordering::SeqCst);
+
+        let mut remaining = budget;
+        loop {
+            let start = self.vm.instruction_count();
+            let mut hits = Vec::new();
+            let mut requested = false;
+            let reason = {
+                let entries = &self.entries;
+                self.vm.run_bounded(remaining, |e: &Event| {
+                    let hit = match *e {
+                        Event::Fetch { pc } => entries.iter().any(|en| en.breakpoint == Breakpoint::Instruction(pc as u16)),
+                        Event::Executed { pc, effects, reads, .. } => {
+                            let before = hits.len();
+                            watch_hits(entries, pc, effects, reads, &mut hits);
+                            hits.len() > before
+                        },
+                    };
+                    if stop(e) { requested = true; }
+                    requested || hit
+                })
+            };
+            if reason == StopReason::Interrupted {
+                if !self.run_meta_commands() { return reason; }
+            } else if reason != StopReason::BreakpointHit {
+                return reason;
+            } else {
+                // a watchpoint stops after its instruction, so if one fired
+                // we never got as far as fetching the next
+                let pc = self.vm.cpu().pc();
+                let mut ids = if hits.is_empty() {
+                    self.entries.iter().filter(|e| e.breakpoint == Breakpoint::Instruction(pc as u16)).map(|e| e.id).collect()
+                } else {
+                    hits.iter().map(|h| h.id).collect::<Vec<_>>()
+                };
+                ids.dedup();
+                ids.retain(|&id| self.triggers(id));
+
+                if requested || !ids.is_empty() {
+                    hits.retain(|h| ids.contains(&h.id));
+                    if hits.is_empty() { self.stopped_at = ids; }
+                    self.hits = hits;
+                    return reason;
+                }
+            }
+
+            let executed = self.vm.instruction_count() - start;
+            remaining = remaining.map(|b| b - executed);
+            if remaining == Some(0) { return StopReason::BudgetExhausted; }
+
+            // carrying on past a watchpoint, the next run starts without
+            // fetching, so `stop` and any breakpoint here see that fetch now
+            if !hits.is_empty() {
+                let pc = self.vm.cpu().pc();
+                let mut ids = self.entries.iter().filter(|e| e.breakpoint == Breakpoint::Instruction(pc as u16)).map(|e| e.id).collect::<Vec<_>>();
+                ids.retain(|&id| self.triggers(id));
+                if stop(&Event::Fetch { pc: pc }) || !ids.is_empty() {
+                    self.stopped_at = ids;
+                    return StopReason::BreakpointHit;
+                }
+            }
+        }
+    }
+
+    /// Runs any meta-commands that interrupted the program. Returns true to
+    /// carry on running, false if there were none or one wants the prompt.
+    fn run_meta_commands(&mut self) -> bool {
+        let meta = match self.meta {
+            Some(ref meta) => meta.clone(),
+            None => return false,
+        };
+        let mut resume = false;
+        while let Some(line) = meta.take() {
+            resume = meta::execute(&mut self.vm, &line) == meta::Action::Resume;
+            if !resume { break; }
+        }
+        resume
+    }
+
+    /// Decides whether breakpoint `id` really stops this time: counts the
+    /// hit if its condition holds, then uses up any ignore count.
+    fn triggers(&mut self, id: usize) -> bool {
+        let entry = match self.entries.iter_mut().find(|e| e.id == id) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if let Some((ref src, ref expr)) = entry.condition {
+            match expr.eval(&self.vm) {
+                Ok(0) => return false,
+                Ok(_) => {},
+                Err(e) => self.notes.push(format!("Error in condition '{}' of breakpoint {}: {}", src, id, e)),
+            }
+        }
+        entry.hits += 1;
+        if entry.ignore > 0 {
+            entry.ignore -= 1;
+            return false;
+        }
+        let message = match entry.trace {
+            Some((_, ref format)) => format.render(&self.vm),
+            None => return true,
+        };
+        self.log_trace(message);
+        false
+    }
+
+    /// Sends a tracepoint message to the trace file, the console, or the
+    /// notes shown at the next stop.
+    fn log_trace(&mut self, message: String) {
+        let result = match self.trace_file {
+            Some(ref mut f) => writeln!(f, "{}", message),
+            None if self.echo_traces => { println!("{}", message); Ok(()) },
+            None => { self.notes.push(message); Ok(()) },
+        };
+        if let Err(e) = result { self.notes.push(format!("Unable to write trace: {}", e)); }
+    }
+
+    /// Runs for at most `budget` instructions, stopping at breakpoints.
+    fn resume(&mut self, budget: Option<u64>) -> StopReason {
+        self.run(budget, |_: &Event| false)
+    }
+
+    /// Steps one instruction, or runs a `call` until it returns.
+    fn next(&mut self) -> StopReason {
+        let pc = self.vm.cpu().pc();
+        let is_call = self.vm.memory().read(pc) == Some(WORD::from(Opcode::Call));
+        if !is_call { return self.resume(Some(1)); }
+        self.run_until_frame(0)
+    }
+
+    /// `next` `count` times, stopping early for a breakpoint or the end
+    /// of the program.
+    fn next_instructions(&mut self, count: u64) -> StopReason {
+        let mut reason = self.resume(Some(0));
+        for _ in 0..count {
+            reason = self.next();
+            let requested = reason == StopReason::BreakpointHit && self.hits.is_empty() && self.stopped_at.is_empty();
+            if reason != StopReason::BudgetExhausted && !requested { break; }
+        }
+        reason
+    }
+
+    /// Runs until pc has come to the start of a source line `count` times.
+    /// Code with no line information is run through, and with `over` so
+    /// are calls.
+    fn step_lines(&mut self, count: u64, over: bool) -> StopReason {
+        if count == 0 { return self.resume(Some(0)); }
+        let table = self.vm.line_table().clone();
+        let mut left = count;
+        let mut depth = 0i32;
+        self.run(None, |e: &Event| match *e {
+            Event::Fetch { pc } => {
+                if (!over || depth <= 0) && table.starts_line(pc) { left -= 1; }
+                left == 0
+            },
+            Event::Executed { effects, .. } => {
+                for effect in effects.iter() {
+                    match *effect {
+                        Effect::FramePush(_) => depth += 1,
+                        Effect::FramePop(_) => depth -= 1,
+                        _ => {},
+                    }
+                }
+                false
+            },
+        })
+    }
+
+    /// Runs until the innermost call returns to its caller.
+    fn finish(&mut self) -> StopReason {
+        self.run_until_frame(-1)
+    }
+
+    /// Runs until a `ret` leaves the call stack `level` frames deeper than it
+    /// is now. Frames come from the CPU's shadow call stack, which only
+    /// closes one when a `ret` pops that call's own return address, so
+    /// `push`/`pop` on the shared data stack can't confuse it.
+    fn run_until_frame(&mut self, level: i32) -> StopReason {
+        let mut depth = 0i32;
+        let mut returned = false;
+        self.run(None, |e: &Event| match *e {
+            Event::Fetch { .. } => returned,
+            Event::Executed { effects, .. } => {
+                for effect in effects.iter() {
+                    match *effect {
+                        Effect::FramePush(_) => depth += 1,
+                        Effect::FramePop(_) => {
+                            depth -= 1;
+                            // one ret can close several frames
+                            if depth <= level { returned = true; }
+                        },
+                        _ => {},
+                    }
+                }
+                false
+            },
+        })
+    }
+
+    fn report(&mut self, reason: StopReason, out: &mut dyn Write) -> io::Result<()> {
+        self.last_stop = Some(reason.clone());
+        for note in self.notes.iter() {
+            writeln!(out, "{}", note)?;
+        }
+        for hit in self.hits.iter() {
+            let bp = self.entries.iter().find(|e| e.id == hit.id).map(|e| e.breakpoint).unwrap();
+            // name the word when a range is watched
+            let word = match (bp, hit.addr) {
+                (Breakpoint::MemoryRead(a, e), Some(addr)) | (Breakpoint::MemoryWrite(a, e), Some(addr)) if a != e => format!("[{:#06X}] ", addr),
+                _ => String::new(),
+            };
+            match hit.old {
+                Some(old) => writeln!(out, "Watchpoint {} ({}): {}{:#06X} -> {:#06X}", hit.id, bp, word, old, hit.value)?,
+                None      => writeln!(out, "Watchpoint {} ({}): {}value {:#06X}", hit.id, bp, word, hit.value)?,
+            }
+            writeln!(out, "    by {}: {}", self.location(hit.pc), self.instruction_text(hit.pc))?;
+        }
+        for id in self.stopped_at.iter() {
+            writeln!(out, "Breakpoint {} at {}", id, self.location(self.vm.cpu().pc()))?;
+        }
+        match reason {
+            StopReason::BudgetExhausted | StopReason::BreakpointHit => {},
+            StopReason::WaitingForInput => writeln!(out, "Program is waiting for input")?,
+            StopReason::Interrupted => writeln!(out, "Interrupted")?,
+            StopReason::Halted => writeln!(out, "Program halted")?,
+            StopReason::Error(e) => {
+                writeln!(out, "Error: {}", e)?;
+                self.vm.cpu().dump(out)?;
+            },
+        }
+        self.show_location(out)?;
+        self.show_displays(out)
+    }
+
+    fn show_displays(&self, out: &mut dyn Write) -> io::Result<()> {
+        for &(n, ref src, ref expr) in self.displays.iter() {
+            match expr.eval(&self.vm) {
+                Ok(v) => writeln!(out, "{}: {} = {}", n, src, format_value(v))?,
+                Err(e) => writeln!(out, "{}: {} = <{}>", n, src, e)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn show_location(&self, out: &mut dyn Write) -> io::Result<()> {
+        let pc = self.vm.cpu().pc();
+        writeln!(out, "=> {}: {}", self.location(pc), self.instruction_text(pc))?;
+        if let Some((file, line)) = self.vm.line_table().location(pc) {
+            let text = self.sources.get(file).and_then(|lines| line.checked_sub(1).and_then(|l| lines.get(l))).map_or("", |l| l.trim());
+            writeln!(out, "{}  {}", self.position(pc).unwrap(), text)?;
+        }
+        Ok(())
+    }
+
+    /// `hello.asm:5` for an address the line table covers.
+    fn position(&self, addr: usize) -> Option<String> {
+        self.vm.line_table().location(addr).map(|(file, line)| {
+            let name = Path::new(file).file_name().map_or(file.into(), |n| n.to_string_lossy());
+            format!("{}:{}", name, line)
+        })
+    }
+
+    /// An address, with the label it falls under when symbols are loaded:
+    /// `0x05B5 <print_word+3>`.
+    fn location(&self, addr: usize) -> String {
+        match self.vm.symbols().describe(addr) {
+            Some(name) => format!("{:#06X} <{}>", addr, name),
+            None => format!("{:#06X}", addr),
+        }
+    }
+
+    /// The instruction at `addr`, with the label of a jump or call target.
+    fn instruction_text(&self, addr: usize) -> String {
+        let memory = self.vm.memory();
+        let (mut text, _) = disasm::instruction_at(memory, addr);
+        if let Some(name) = disasm::target(memory, addr).and_then(|t| self.vm.symbols().describe(t)) {
+            text.push_str(&format!(" <{}>", name));
+        }
+        text
+    }
+
+    /// A number, a label or `label+N` when symbols are loaded, or
+    /// `FILE:LINE` when there is a line table.
+    fn address(&self, s: &str) -> Option<usize> {
+        if let Some(i) = s.rfind(':') {
+            if let Some(line) = parse_number(&s[i + 1..]) {
+                return self.vm.line_table().address_of(&s[..i], line).map(|(addr, _)| addr);
+            }
+        }
+        parse_number(s).or_else(|| self.vm.symbols().resolve(s))
+    }
+
+    /* COMMANDS */
+
+    fn add_entry(&mut self, breakpoint: Breakpoint, condition: Option<(String, Expr)>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push(Entry { id: id, breakpoint: breakpoint, condition: condition, ignore: 0, hits: 0, commands: Vec::new(), trace: None });
+        id
+    }
+
+    fn cmd_break(&mut self, rest: &str, out: &mut dyn Write) -> io::Result<()> {
+        let (target, condition) = match split_condition(rest) {
+            Ok(split) => split,
+            Err(e) => return writeln!(out, "Error: {}", e),
+        };
+        match self.address(target.trim()) {
+            Some(addr) if addr <= 0x7FFF => {
+                let id = self.add_entry(Breakpoint::Instruction(addr as u16), condition);
+                match self.position(addr) {
+                    Some(position) => writeln!(out, "Breakpoint {} at {}: {}", id, self.location(addr), position),
+                    None => writeln!(out, "Breakpoint {} at {}", id, self.location(addr)),
+                }
+            },
+            _ => writeln!(out, "Usage: break ADDR|LABEL[+N]|FILE:LINE [if EXPR]"),
+        }
+    }
+
+    /// `trace ADDR "FORMAT" [if EXPR]`
+    fn cmd_trace(&mut self, rest: &str, out: &mut dyn Write) -> io::Result<()> {
+        let usage = "Usage: trace ADDR \"FORMAT\" [if EXPR]";
+        let (addr, rest) = rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len()));
+        let rest = rest.trim();
+        let addr = match self.address(addr) {
+            Some(addr) if addr <= 0x7FFF => addr,
+            _ => return writeln!(out, "{}", usage),
+        };
+        if !rest.starts_with('"') { return writeln!(out, "{}", usage); }
+        let end = match rest[1..].find('"') {
+            Some(i) => i + 1,
+            None => return writeln!(out, "{}", usage),
+        };
+        let src = &rest[1..end];
+        let after = rest[end + 1..].trim();
+
+        let format = match expr::parse_format(src) {
+            Ok(format) => format,
+            Err(e) => return writeln!(out, "Error: {}", e),
+        };
+        let condition = if after.is_empty() {
+            None
+        } else if after.starts_with("if ") {
+            match expr::parse(&after[3..]) {
+                Ok(e) => Some((after[3..].trim().to_string(), e)),
+                Err(e) => return writeln!(out, "Error: {}", e),
+            }
+        } else {
+            return writeln!(out, "{}", usage);
+        };
+
+        let id = self.add_entry(Breakpoint::Instruction(addr as u16), condition);
+        self.entries.last_mut().unwrap().trace = Some((src.to_string(), format));
+        writeln!(out, "Tracepoint {} at {}", id, self.location(addr))
+    }
+
+    fn cmd_tracelog(&mut self, rest: &str, out: &mut dyn Write) -> io::Result<()> {
+        if rest.is_empty() {
+            self.trace_file = None;
+            return writeln!(out, "Trace messages go to the console");
+        }
+        match File::create(rest) {
+            Ok(f) => {
+                self.trace_file = Some(f);
+                writeln!(out, "Trace messages go to {}", rest)
+            },
+            Err(e) => writeln!(out, "Unable to create {}: {}", rest, e),
+        }
+    }
+
+    fn cmd_watch(&mut self, read: bool, rest: &str, out: &mut dyn Write) -> io::Result<()> {
+        let (target, condition) = match split_condition(rest) {
+            Ok(split) => split,
+            Err(e) => return writeln!(out, "Error: {}", e),
+        };
+        let args = target.split_whitespace().map(|a| a.trim_matches(|c| c == '[' || c == ']')).collect::<Vec<_>>();
+        let first = args.get(0).cloned().unwrap_or("");
+        let end = args.get(1).map(|a| self.address(a));
+        let bp = match (parse_register(first), self.address(first), end, args.len()) {
+            (Some(r), _, None, 1) => if read { Breakpoint::RegisterRead(r) } else { Breakpoint::RegisterWrite(r) },
+            (None, Some(a), None, 1) if a < MEMORY_SIZE => if read { Breakpoint::MemoryRead(a, a) } else { Breakpoint::MemoryWrite(a, a) },
+            (None, Some(a), Some(Some(e)), 2) if a <= e && e < MEMORY_SIZE =>
+                if read { Breakpoint::MemoryRead(a, e) } else { Breakpoint::MemoryWrite(a, e) },
+            _ => return writeln!(out, "Usage: {} rN|ADDR [END] [if EXPR]", if read { "rwatch" } else { "watch" }),
+        };
+        let id = self.add_entry(bp, condition);
+        writeln!(out, "Watchpoint {} ({})", id, bp)
+    }
+
+    fn cmd_cond(&mut self, rest: &str, out: &mut dyn Write) -> io::Result<()> {
+        let mut parts = rest.splitn(2, ' ');
+        let id = parts.next().and_then(parse_number);
+        let src = parts.next().map(|s| s.trim()).unwrap_or("");
+        let condition = if src.is_empty() {
+            None
+        } else {
+            match expr::parse(src) {
+                Ok(e) => Some((src.to_string(), e)),
+                Err(e) => return writeln!(out, "Error: {}", e),
+            }
+        };
+        match id.and_then(|id| self.entries.iter_mut().find(|e| e.id == id)) {
+            Some(entry) => { entry.condition = condition; Ok(()) },
+            None => writeln!(out, "Usage: cond N [EXPR]"),
+        }
+    }
+
+    fn cmd_ignore(&mut self, args: &[&str], out: &mut dyn Write) -> io::Result<()> {
+        let id = args.get(0).and_then(|a| parse_number(a));
+        let count = args.get(1).and_then(|a| parse_number(a));
+        match (id.and_then(|id| self.entries.iter_mut().find(|e| e.id == id)), count) {
+            (Some(entry), Some(count)) => {
+                entry.ignore = count as u64;
+                writeln!(out, "Will ignore next {} crossings of breakpoint {}", count, entry.id)
+            },
+            _ => writeln!(out, "Usage: ignore N COUNT"),
+        }
+    }
+
+    fn cmd_breakpoints(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.entries.is_empty() { return writeln!(out, "No breakpoints"); }
+        for entry in self.entries.iter() {
+            match (entry.breakpoint, &entry.trace) {
+                (Breakpoint::Instruction(addr), &Some((ref src, _))) => write!(out, "{:>3}  trace at {} \"{}\"", entry.id, self.location(addr as usize), src)?,
+                (Breakpoint::Instruction(addr), &None) => write!(out, "{:>3}  break at {}", entry.id, self.location(addr as usize))?,
+                _ => write!(out, "{:>3}  {}", entry.id, entry.breakpoint)?,
+            }
+            if let Some((ref src, _)) = entry.condition { write!(out, " if {}", src)?; }
+            if entry.hits > 0 { write!(out, ", hit {} times", entry.hits)?; }
+            if entry.ignore > 0 { write!(out, ", ignoring next {}", entry.ignore)?; }
+            writeln!(out, "")?;
+            for line in entry.commands.iter() {
+                writeln!(out, "        {}", line)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn cmd_delete(&mut self, args: &[&str], out: &mut dyn Write) -> io::Result<()> {
+        match args.get(0).and_then(|a| parse_number(a)) {
+            Some(id) => {
+                let before = self.entries.len();
+                self.entries.retain(|e| e.id != id);
+                if self.entries.len() == before { writeln!(out, "No breakpoint {}", id)?; }
+                Ok(())
+            },
+            None => writeln!(out, "Usage: delete N"),
+        }
+    }
+
+    fn cmd_display(&mut self, rest: &str, out: &mut dyn Write) -> io::Result<()> {
+        if rest.is_empty() { return self.show_displays(out); }
+        match expr::parse(rest) {
+            Ok(e) => {
+                let n = self.next_display;
+                self.next_display += 1;
+                self.displays.push((n, rest.to_string(), e));
+                let (_, ref src, ref e) = self.displays[self.displays.len() - 1];
+                match e.eval(&self.vm) {
+                    Ok(v) => writeln!(out, "{}: {} = {}", n, src, format_value(v)),
+                    Err(e) => writeln!(out, "{}: {} = <{}>", n, src, e),
+                }
+            },
+            Err(e) => writeln!(out, "Error: {}", e),
+        }
+    }
+
+    fn cmd_undisplay(&mut self, args: &[&str], out: &mut dyn Write) -> io::Result<()> {
+        match args.get(0).and_then(|a| parse_number(a)) {
+            Some(n) => { self.displays.retain(|&(i, _, _)| i != n); Ok(()) },
+            None => writeln!(out, "Usage: undisplay N"),
+        }
+    }
+
+    fn cmd_regs(&self, out: &mut dyn Write) -> io::Result<()> {
+        let cpu = self.vm.cpu();
+        writeln!(out, "pc  {:#06X}   state {:?}", cpu.pc(), cpu.state())?;
+        for (r, v) in cpu.registers().iter().enumerate() {
+            write!(out, "r{}  {:#06X} {:>5}", r, v, v)?;
+            if r % 4 == 3 { writeln!(out, "")?; } else { write!(out, "   ")?; }
+        }
+        Ok(())
+    }
+
+    fn cmd_stack(&self, out: &mut dyn Write) -> io::Result<()> {
+        let words = self.vm.stack().words();
+        if words.is_empty() { return writeln!(out, "Stack is empty"); }
+        for (depth, w) in words.iter().rev().enumerate() {
+            writeln!(out, "{:>4}  {:#06X} {:>5}", depth, w, w)?;
+        }
+        Ok(())
+    }
+
+    fn cmd_backtrace(&self, out: &mut dyn Write) -> io::Result<()> {
+        let frames = self.vm.backtrace();
+        let at = |addr| self.position(addr).map_or(String::new(), |p| format!(" at {}", p));
+        let pc = self.vm.cpu().pc();
+        writeln!(out, "#0  {}{}", self.location(pc), at(pc))?;
+        for (n, frame) in frames.iter().enumerate() {
+            writeln!(out, "#{}  {}{}  (called {} at stack depth {})", n + 1, self.location(frame.caller), at(frame.caller), self.location(frame.callee), frame.depth)?;
+        }
+        Ok(())
+    }
+
+    fn cmd_examine(&self, cmd: &str, args: &[&str], out: &mut dyn Write) -> io::Result<()> {
+        let count = if cmd == "x" { Some(1) } else { parse_number(&cmd[2..]) };
+        let addr = args.get(0).and_then(|a| self.address(a));
+        let (count, addr) = match (count, addr) {
+            (Some(c), Some(a)) if a < MEMORY_SIZE => (c, a),
+            _ => return writeln!(out, "Usage: x/N ADDR"),
+        };
+
+        let memory = self.vm.memory();
+        let end = addr + count.min(MEMORY_SIZE - addr);
+        for start in (addr..end).step_by(8) {
+            let words = (start..end.min(start + 8)).filter_map(|a| memory.read(a)).collect::<Vec<_>>();
+            let hex = words.iter().map(|w| format!("{:04X}", w)).collect::<Vec<_>>().join(" ");
+            let ascii = words.iter().map(|&w| if w >= 0x20 && w < 0x7F { w as u8 as char } else { '.' }).collect::<String>();
+            writeln!(out, "{:#06X}: {:<39}  |{}|", start, hex, ascii)?;
+        }
+        Ok(())
+    }
+
+    fn cmd_who_wrote(&self, args: &[&str], out: &mut dyn Write) -> io::Result<()> {
+        let provenance = match self.vm.provenance() {
+            Some(p) => p,
+            None => return writeln!(out, "Write tracking is off; `track on` starts it"),
+        };
+        let target = args.get(0).map(|a| a.trim_matches(|c| c == '[' || c == ']')).unwrap_or("");
+        let (name, value, writes) = match (parse_register(target), self.address(target)) {
+            (Some(r), _) => (format!("r{}", r), self.vm.cpu().register_get(r), provenance.register(r).into_iter().collect::<Vec<_>>()),
+            (None, Some(a)) if a < MEMORY_SIZE => {
+                let history = provenance.history(a);
+                let writes = if history.is_empty() { provenance.memory(a).into_iter().collect() } else { history.to_vec() };
+                (format!("[{:#06X}]", a), self.vm.memory().read(a).unwrap(), writes)
+            },
+            _ => return writeln!(out, "Usage: who-wrote rN|ADDR"),
+        };
+
+        writeln!(out, "{} = {:#06X}", name, value)?;
+        if writes.is_empty() { return writeln!(out, "    no write recorded"); }
+        for w in writes {
+            let by = match w.pc {
+                Some(pc) => format!("{}: {}", self.location(pc), self.instruction_text(pc)),
+                None => "the debugger".to_string(),
+            };
+            writeln!(out, "    {:#06X} -> {:#06X} at instruction {} by {}", w.old, w.new, w.count, by)?;
+        }
+        Ok(())
+    }
+
+    fn cmd_track(&mut self, args: &[&str], out: &mut dyn Write) -> io::Result<()> {
+        match args.get(0) {
+            Some(&"off") => {
+                self.vm.set_provenance(false);
+                return writeln!(out, "Write tracking is off");
+            },
+            Some(&"on") => {
+                // starting again forgets everything recorded before
+                if self.vm.provenance().is_none() { self.vm.set_provenance(true); }
+                return writeln!(out, "Write tracking is on");
+            },
+            _ => {},
+        }
+        let start = args.get(0).and_then(|a| self.address(a));
+        let end = args.get(1).map_or(start, |a| self.address(a));
+        let provenance = match self.vm.provenance_mut() {
+            Some(p) => p,
+            None => return writeln!(out, "Write tracking is off; `track on` starts it"),
+        };
+        if args.is_empty() {
+            if provenance.ranges().is_empty() { return writeln!(out, "No write history is being kept"); }
+            for &(start, end) in provenance.ranges() {
+                writeln!(out, "{:#06X}..{:#06X}", start, end)?;
+            }
+            return Ok(());
+        }
+
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end && end < MEMORY_SIZE => {
+                provenance.keep_history(start, end);
+                writeln!(out, "Keeping every write to {:#06X}..{:#06X}", start, end)
+            },
+            _ => writeln!(out, "Usage: track [ADDR [END]]"),
+        }
+    }
+
+    fn cmd_disas(&self, args: &[&str], out: &mut dyn Write) -> io::Result<()> {
+        let pc = self.vm.cpu().pc();
+        let memory = self.vm.memory();
+        let (start, count) = match args.get(0).and_then(|a| self.address(a)) {
+            Some(addr) => (addr, args.get(1).and_then(|a| parse_number(a)).unwrap_or(10)),
+            None => (disasm::start_before(memory, pc, 4), 10),
+        };
+        for (addr, _) in disasm::listing(memory, start, count) {
+            if let Some(name) = self.vm.symbols().name(addr) { writeln!(out, "{}:", name)?; }
+            writeln!(out, "{} {:#06X}: {}", if addr == pc { "=>" } else { "  " }, addr, self.instruction_text(addr))?;
+        }
+        Ok(())
+    }
+
+    /// `sym LABEL[+N]|ADDR`: converts between labels and addresses.
+    fn cmd_sym(&self, args: &[&str], out: &mut dyn Write) -> io::Result<()> {
+        let symbols = self.vm.symbols();
+        match args.get(0) {
+            None if symbols.is_empty() => writeln!(out, "No symbols loaded"),
+            None => writeln!(out, "{} symbols loaded", symbols.len()),
+            Some(a) => match (parse_number(a), symbols.resolve(a)) {
+                (Some(addr), _) => match symbols.describe(addr) {
+                    Some(name) => writeln!(out, "{:#06X} is {}", addr, name),
+                    None => writeln!(out, "No symbol at or before {:#06X}", addr),
+                },
+                (None, Some(addr)) => writeln!(out, "{} is {:#06X}", a, addr),
+                (None, None) => writeln!(out, "No symbol '{}'", a),
+            },
+        }
+    }
+
+    fn cmd_symbol_file(&mut self, args: &[&str], out: &mut dyn Write) -> io::Result<()> {
+        let file = match args.get(0) {
+            Some(file) => file,
+            None => return writeln!(out, "Usage: symbol-file FILE"),
+        };
+        match Symbols::load(file) {
+            Ok(symbols) => {
+                writeln!(out, "Loaded {} symbols from {}", symbols.len(), file)?;
+                self.vm.set_symbols(symbols);
+                Ok(())
+            },
+            Err(e) => writeln!(out, "Unable to load {}: {}", file, e),
+        }
+    }
+
+    fn cmd_line_file(&mut self, args: &[&str], out: &mut dyn Write) -> io::Result<()> {
+        let file = match args.get(0) {
+            Some(file) => file,
+            None => return writeln!(out, "Usage: line-file FILE"),
+        };
+        match LineTable::load(file) {
+            Ok(table) => {
+                writeln!(out, "Loaded lines of {} from {}", table.files().join(", "), file)?;
+                self.vm.set_line_table(table);
+                self.load_sources();
+                Ok(())
+            },
+            Err(e) => writeln!(out, "Unable to load {}: {}", file, e),
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,100 @@
+//! Searching memory for text and word patterns, for the debugger's `find`
+//! command and `--find`.
+//!
+//! ```text
+//! find "Foothills"         text stored one character per word
+//! find 0x0011 ? 0x8000     a run of words, `?` matching any word
+//! find pstr "Foothills"    length-prefixed strings containing the text
+//! ```
+
+use std::io::{self,Write};
+use synacor::WORD;
+use synacor::parse_number;
+
+/// How many matches are listed before the rest are only counted.
+const MAX_SHOWN: usize = 50;
+
+pub const USAGE: &'static str = "Usage: find \"TEXT\" | find WORD|? ... | find pstr \"TEXT\"";
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Pattern {
+    Text(String),
+    Words(Vec<Option<WORD>>),
+    Prefixed(String),
+}
+
+fn quoted(s: &str) -> Result<String,String> {
+    if s.len() > 2 && s.starts_with('"') && s.ends_with('"') {
+        Ok(s[1..s.len() - 1].to_string())
+    } else {
+        Err(USAGE.to_string())
+    }
+}
+
+/// Parses the arguments of `find`.
+pub fn parse(args: &str) -> Result<Pattern,String> {
+    let args = args.trim();
+    if args.starts_with("pstr") {
+        return quoted(args["pstr".len()..].trim()).map(Pattern::Prefixed);
+    }
+    if args.starts_with('"') {
+        return quoted(args).map(Pattern::Text);
+    }
+
+    let words = args.split_whitespace().map(|w| match (w, parse_number(w)) {
+        ("?", _) => Ok(None),
+        (_, Some(n)) if n <= 0xFFFF => Ok(Some(n as WORD)),
+        _ => Err(format!("'{}' is not a word or '?'", w)),
+    }).collect::<Result<Vec<_>,_>>()?;
+    if words.is_empty() { return Err(USAGE.to_string()); }
+    Ok(Pattern::Words(words))
+}
+
+/// The length-prefixed string at `addr`, if the length word there is
+/// followed by that many printable characters.
+pub fn prefixed_at(words: &[WORD], addr: usize) -> Option<String> {
+    let len = *words.get(addr)? as usize;
+    let chars = words.get(addr + 1..addr + 1 + len)?;
+    if len > 0 && chars.iter().all(|&c| (c >= 0x20 && c < 0x7F) || c == 0x0A) {
+        Some(chars.iter().map(|&c| c as u8 as char).collect())
+    } else {
+        None
+    }
+}
+
+fn find_words(words: &[WORD], pattern: &[Option<WORD>]) -> Vec<usize> {
+    if pattern.len() > words.len() { return Vec::new(); }
+    words.windows(pattern.len()).enumerate()
+        .filter(|&(_, run)| run.iter().zip(pattern).all(|(&w, p)| p.map_or(true, |p| p == w)))
+        .map(|(addr, _)| addr)
+        .collect()
+}
+
+/// Every address in `words` where `pattern` starts.
+pub fn search(words: &[WORD], pattern: &Pattern) -> Vec<usize> {
+    match *pattern {
+        Pattern::Text(ref text) => find_words(words, &text.bytes().map(|b| Some(b as WORD)).collect::<Vec<_>>()),
+        Pattern::Words(ref pattern) => find_words(words, pattern),
+        Pattern::Prefixed(ref text) => (0..words.len())
+            .filter(|&addr| prefixed_at(words, addr).map_or(false, |s| s.contains(&text[..])))
+            .collect(),
+    }
+}
+
+/// Lists where `pattern` occurs in `words`, with the whole string for
+/// length-prefixed matches.
+pub fn report(words: &[WORD], pattern: &Pattern, out: &mut dyn Write) -> io::Result<()> {
+    let found = search(words, pattern);
+    for &addr in found.iter().take(MAX_SHOWN) {
+        match *pattern {
+            Pattern::Prefixed(_) => writeln!(out, "{:#06X}: {:?}", addr, prefixed_at(words, addr).unwrap())?,
+            _ => writeln!(out, "{:#06X}", addr)?,
+        }
+    }
+    if found.len() > MAX_SHOWN { writeln!(out, "... and {} more", found.len() - MAX_SHOWN)?; }
+    match found.len() {
+        0 => writeln!(out, "Not found"),
+        1 => writeln!(out, "1 match"),
+        n => writeln!(out, "{} matches", n),
+    }
+}
\ No newline at end of file
@@ -0,0 +1,397 @@
+//! Full-screen debugger (`-g --tui`): disassembly, registers, stack, memory
+//! and the program's output each get their own pane.
+//!
+//! Keys: `s` step, `n` next, `c` continue (Esc or Ctrl-C breaks back in),
+//! `u` step back, `b` toggle a breakpoint at the selected line, `j`/`k` or
+//! the arrows move the selection, `.` returns it to pc, PageUp/PageDown
+//! scroll memory, `g` moves memory to an address, `i` types a line into the
+//! program, `:` runs any command from the line debugger, and `q` quits.
+
+use std::io::{self,Write};
+use std::thread;
+use std::time::Duration;
+use termion::{async_stdin,clear,cursor,style,terminal_size};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+use synacor::{parse_number,BufferConsole,StopReason};
+use super::{disasm,Breakpoint,Debugger};
+
+const HELP_LINE: &'static str = "s step  n next  c continue  u back  b break  i input  g goto  : command  q quit";
+const MEMORY_ROWS: usize = 8;
+const MEMORY_ROW_WORDS: usize = 8;
+const LAST_ADDRESS: usize = 0x7FFF;
+// where the memory pane starts when it shows the last page
+const LAST_PAGE: usize = LAST_ADDRESS + 1 - MEMORY_ROWS * MEMORY_ROW_WORDS;
+/// Instructions to run between checks for a key to break in with.
+const RUN_CHUNK: u64 = 10_000;
+
+#[derive(Debug,PartialEq)]
+enum Mode {
+    Normal,
+    Input(String),
+    Command(String),
+    Goto(String),
+}
+
+struct Tui<'a> {
+    debugger: &'a mut Debugger,
+    console: BufferConsole,
+    transcript: String,
+    status: String,
+    selected: usize,
+    memory_addr: usize,
+    mode: Mode,
+    keys: Box<dyn Iterator<Item=io::Result<Key>>>, // never blocks
+}
+
+/// Runs the debugger full-screen until the user quits. `console` must be
+/// the console the debugger's VM was given, so its output can be shown.
+pub fn run(debugger: &mut Debugger, console: BufferConsole) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut screen = AlternateScreen::from(stdout.lock().into_raw_mode()?);
+    write!(screen, "{}", cursor::Hide)?;
+
+    let pc = debugger.vm().cpu().pc();
+    let mut tui = Tui {
+        debugger: debugger,
+        console: console,
+        transcript: String::new(),
+        status: HELP_LINE.to_string(),
+        selected: pc,
+        memory_addr: 0,
+        mode: Mode::Normal,
+        keys: Box::new(async_stdin().keys()),
+    };
+
+    tui.draw(&mut screen)?;
+    loop {
+        match tui.keys.next() {
+            Some(key) => {
+                if !tui.key(key?) { break; }
+                tui.draw(&mut screen)?;
+            },
+            None => thread::sleep(Duration::from_millis(20)),
+        }
+    }
+    write!(screen, "{}", cursor::Show)?;
+    screen.flush()
+}
+
+/// Draws a bordered pane with `title` and as many of `lines` as fit.
+fn pane(buf: &mut String, x: u16, y: u16, w: u16, h: u16, title: &str, lines: &[String]) {
+    if w < 4 || h < 2 { return; }
+    let inner = (w - 2) as usize;
+    let top = format!("┌ {} {}", title, "─".repeat(inner.saturating_sub(title.chars().count() + 2)));
+    buf.push_str(&format!("{}{}┐", cursor::Goto(x, y), top.chars().take(inner + 1).collect::<String>()));
+    for row in 0..(h - 2) {
+        let text = lines.get(row as usize).map(|l| l.chars().take(inner).collect::<String>()).unwrap_or_default();
+        buf.push_str(&format!("{}│{:<width$}│", cursor::Goto(x, y + 1 + row), text, width = inner));
+    }
+    buf.push_str(&format!("{}└{}┘", cursor::Goto(x, y + h - 1), "─".repeat(inner)));
+}
+
+impl<'a> Tui<'a> {
+    /// Runs a line debugger command, keeping what it said for the status line.
+    fn exec(&mut self, command: &str) {
+        let mut out = Vec::new();
+        self.debugger.last_stop = None;
+        let _ = self.debugger.command(command, &mut out);
+        let reason = self.debugger.last_stop.take();
+        self.show(&out, reason);
+    }
+
+    /// Continues in chunks, checking between them for Esc or Ctrl-C, as raw
+    /// mode stops Ctrl-C from interrupting the program the usual way.
+    fn resume(&mut self) {
+        let reason = loop {
+            let reason = self.debugger.resume(Some(RUN_CHUNK));
+            if reason != StopReason::BudgetExhausted { break reason; }
+            if self.break_pressed() { break StopReason::Interrupted; }
+        };
+        let mut out = Vec::new();
+        let _ = self.debugger.report(reason.clone(), &mut out);
+        self.show(&out, Some(reason));
+    }
+
+    /// Whether Esc or Ctrl-C was typed. Other keys are dropped.
+    fn break_pressed(&mut self) -> bool {
+        while let Some(key) = self.keys.next() {
+            match key {
+                Ok(Key::Esc) | Ok(Key::Ctrl('c')) => return true,
+                _ => {},
+            }
+        }
+        false
+    }
+
+    /// Shows what a command said in the status line, and the program's
+    /// output. `reason` is why the program stopped, if the command ran it.
+    fn show(&mut self, out: &[u8], reason: Option<StopReason>) {
+        let said = String::from_utf8_lossy(out).into_owned();
+        self.status = said.lines().filter(|l| !l.starts_with("=> ")).collect::<Vec<_>>().join(" | ");
+        if self.status.is_empty() { self.status = HELP_LINE.to_string(); }
+
+        self.transcript.push_str(&self.console.take_output());
+        self.selected = self.debugger.vm().cpu().pc();
+        if reason == Some(StopReason::WaitingForInput) { self.mode = Mode::Input(String::new()); }
+    }
+
+    fn toggle_breakpoint(&mut self) {
+        let bp = Breakpoint::Instruction(self.selected as u16);
+        match self.debugger.entries.iter().position(|e| e.breakpoint == bp) {
+            Some(i) => { self.debugger.entries.remove(i); },
+            None => { self.debugger.add_entry(bp, None); },
+        }
+    }
+
+    /// Handles one key press. Returns false to quit.
+    fn key(&mut self, key: Key) -> bool {
+        let mode = ::std::mem::replace(&mut self.mode, Mode::Normal);
+        match mode {
+            Mode::Normal => match key {
+                Key::Char('q') => return false,
+                Key::Char('s') => self.exec("step"),
+                Key::Char('n') => self.exec("next"),
+                Key::Char('c') => self.resume(),
+                Key::Char('u') => self.exec("rstep"),
+                Key::Char('b') => self.toggle_breakpoint(),
+                Key::Char('j') | Key::Down => {
+                    let (_, len) = disasm::instruction_at(self.debugger.vm().memory(), self.selected);
+                    self.selected = (self.selected + len).min(LAST_ADDRESS);
+                },
+                Key::Char('k') | Key::Up => self.selected = disasm::start_before(self.debugger.vm().memory(), self.selected, 1),
+                Key::Char('.') => self.selected = self.debugger.vm().cpu().pc(),
+                Key::PageDown => self.memory_addr = (self.memory_addr + MEMORY_ROWS * MEMORY_ROW_WORDS).min(LAST_PAGE),
+                Key::PageUp => self.memory_addr = self.memory_addr.saturating_sub(MEMORY_ROWS * MEMORY_ROW_WORDS),
+                Key::Char('i') => self.mode = Mode::Input(String::new()),
+                Key::Char(':') => self.mode = Mode::Command(String::new()),
+                Key::Char('g') => self.mode = Mode::Goto(String::new()),
+                _ => {},
+            },
+            Mode::Input(_) | Mode::Command(_) | Mode::Goto(_) if key == Key::Esc => {},
+            Mode::Input(mut line) => match key {
+                Key::Char('\n') => {
+                    line.push('\n');
+                    self.transcript.push_str(&line);
+                    self.console.push_input(&line);
+                    self.resume();
+                },
+                Key::Backspace => { line.pop(); self.mode = Mode::Input(line); },
+                Key::Char(c) => { line.push(c); self.mode = Mode::Input(line); },
+                _ => self.mode = Mode::Input(line),
+            },
+            Mode::Command(mut line) => match key {
+                Key::Char('\n') if line.trim() == "c" || line.trim() == "continue" => self.resume(),
+                Key::Char('\n') => self.exec(&line),
+                Key::Backspace => { line.pop(); self.mode = Mode::Command(line); },
+                Key::Char(c) => { line.push(c); self.mode = Mode::Command(line); },
+                _ => self.mode = Mode::Command(line),
+            },
+            Mode::Goto(mut line) => match key {
+                Key::Char('\n') => match parse_number(line.trim()) {
+                    Some(addr) if addr <= LAST_ADDRESS => self.memory_addr = addr.min(LAST_PAGE),
+                    Some(_) | None => self.status = format!("Not an address: {}", line),
+                },
+                Key::Backspace => { line.pop(); self.mode = Mode::Goto(line); },
+                Key::Char(c) => { line.push(c); self.mode = Mode::Goto(line); },
+                _ => self.mode = Mode::Goto(line),
+            },
+        }
+        true
+    }
+
+    fn draw(&self, screen: &mut dyn Write) -> io::Result<()> {
+        let (w, h) = terminal_size()?;
+        let vm = self.debugger.vm();
+        let pc = vm.cpu().pc();
+        let mut buf = format!("{}", clear::All);
+
+        let memory_h = MEMORY_ROWS as u16 + 2;
+        let output_h = (h / 3).max(5);
+        let top_h = h.saturating_sub(memory_h + output_h + 1).max(11);
+        let left_w = w / 2;
+        let right_w = w - left_w;
+
+        // disassembly, with the selected line roughly in the middle
+        let rows = top_h.saturating_sub(2) as usize;
+        let start = disasm::start_before(vm.memory(), self.selected, rows / 2);
+        let listing = disasm::listing(vm.memory(), start, rows).into_iter().map(|(addr, text)| {
+            let bp = self.debugger.entries.iter().any(|e| e.breakpoint == Breakpoint::Instruction(addr as u16));
+            let mark = if addr == pc { "=>" } else if addr == self.selected { "> " } else { "  " };
+            format!("{}{} {:#06X}: {}", if bp { "*" } else { " " }, mark, addr, text)
+        }).collect::<Vec<_>>();
+        pane(&mut buf, 1, 1, left_w, top_h, "Disassembly", &listing);
+
+        let cpu = vm.cpu();
+        let mut registers = vec![format!("pc {:#06X}  {:?}", pc, cpu.state())];
+        for pair in cpu.registers().chunks(2).enumerate() {
+            let (i, regs) = pair;
+            registers.push(format!("r{} {:#06X} {:>5}   r{} {:#06X} {:>5}", i * 2, regs[0], regs[0], i * 2 + 1, regs[1], regs[1]));
+        }
+        pane(&mut buf, left_w + 1, 1, right_w, 7, "Registers", &registers);
+
+        let stack = vm.stack().words().iter().rev().enumerate()
+            .map(|(depth, w)| format!("{:>4}  {:#06X} {:>5}", depth, w, w))
+            .collect::<Vec<_>>();
+        pane(&mut buf, left_w + 1, 8, right_w, top_h.saturating_sub(7), &format!("Stack ({})", stack.len()), &stack);
+
+        let mut dump = Vec::new();
+        let addr = self.memory_addr.to_string();
+        let _ = self.debugger.cmd_examine(&format!("x/{}", MEMORY_ROWS * MEMORY_ROW_WORDS), &[&addr[..]], &mut dump);
+        let dump = String::from_utf8_lossy(&dump).lines().map(|l| l.to_string()).collect::<Vec<_>>();
+        pane(&mut buf, 1, top_h + 1, w, memory_h, "Memory", &dump);
+
+        let wrap = (w as usize).saturating_sub(2).max(1);
+        let mut output = Vec::new();
+        for line in self.transcript.split('\n') {
+            let chars = line.chars().collect::<Vec<_>>();
+            if chars.is_empty() { output.push(String::new()); }
+            for chunk in chars.chunks(wrap) { output.push(chunk.iter().collect()); }
+        }
+        let shown = output_h.saturating_sub(2) as usize;
+        let skip = output.len().saturating_sub(shown);
+        pane(&mut buf, 1, top_h + memory_h + 1, w, output_h, "Output", &output[skip..]);
+
+        let status = match self.mode {
+            Mode::Normal => self.status.clone(),
+            Mode::Input(ref line) => format!("input> {}", line),
+            Mode::Command(ref line) => format!(":{}", line),
+            Mode::Goto(ref line) => format!("memory address: {}", line),
+        };
+        buf.push_str(&format!("{}{}{}{}", cursor::Goto(1, h), style::Invert, status.chars().take(w as usize).collect::<String>(), style::Reset));
+
+        screen.write_all(buf.as_bytes())?;
+        screen.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use termion::cursor::Goto;
+    use termion::event::Key;
+    use synacor::{BufferConsole,Vm};
+    use debugger::{Breakpoint,Debugger};
+    use super::{pane,Mode,Tui,HELP_LINE,LAST_ADDRESS,LAST_PAGE};
+
+    fn tui<'a>(debugger: &'a mut Debugger, console: BufferConsole, keys: Vec<Key>) -> Tui<'a> {
+        Tui {
+            debugger: debugger,
+            console: console,
+            transcript: String::new(),
+            status: HELP_LINE.to_string(),
+            selected: 0,
+            memory_addr: 0,
+            mode: Mode::Normal,
+            keys: Box::new(keys.into_iter().map(|k| Ok::<Key, io::Error>(k))),
+        }
+    }
+
+    fn type_keys(tui: &mut Tui, text: &str) {
+        for c in text.chars() { assert!(tui.key(Key::Char(c))); }
+    }
+
+    #[test]
+    fn test_keys() {
+        // in r0; out r0; halt
+        let console = BufferConsole::new();
+        let mut vm = Vm::with_console(Box::new(console.clone()));
+        vm.load_memory(vec![20, 32768, 19, 32768, 0]).unwrap();
+        let mut debugger = Debugger::new(vm);
+        let mut tui = tui(&mut debugger, console, vec![]);
+
+        type_keys(&mut tui, ":b 4");
+        assert_eq!(Mode::Command("b 4".to_string()), tui.mode);
+        tui.key(Key::Backspace);
+        type_keys(&mut tui, "2\n");
+        assert_eq!(Mode::Normal, tui.mode);
+        assert_eq!("Breakpoint 1 at 0x0002", tui.status);
+
+        type_keys(&mut tui, "g1x");
+        assert_eq!(Mode::Goto("1x".to_string()), tui.mode);
+        type_keys(&mut tui, "\n");
+        assert_eq!("Not an address: 1x", tui.status);
+        type_keys(&mut tui, "g0x10\n");
+        assert_eq!(0x10, tui.memory_addr);
+
+        type_keys(&mut tui, "i");
+        tui.key(Key::Esc);
+        assert_eq!(Mode::Normal, tui.mode);
+
+        // `in` finds nothing to read and asks for a line
+        type_keys(&mut tui, "c");
+        assert_eq!(Mode::Input(String::new()), tui.mode);
+        type_keys(&mut tui, "A\n");
+        assert_eq!(Mode::Normal, tui.mode);
+        assert_eq!("A\n", tui.transcript);
+        assert!(tui.status.contains("Breakpoint 1 at 0x0002"), "{}", tui.status);
+        type_keys(&mut tui, "c");
+        assert_eq!("A\nA", tui.transcript);
+        assert!(tui.status.contains("Program halted"), "{}", tui.status);
+
+        type_keys(&mut tui, "k");
+        assert_eq!(2, tui.selected);
+        type_keys(&mut tui, "b");
+        assert!(!tui.debugger.entries.iter().any(|e| e.breakpoint == Breakpoint::Instruction(2)));
+        assert!(!tui.key(Key::Char('q')));
+    }
+
+    #[test]
+    fn test_break_key() {
+        // jmp 0
+        let console = BufferConsole::new();
+        let mut vm = Vm::with_console(Box::new(console.clone()));
+        vm.load_memory(vec![6, 0]).unwrap();
+        let mut debugger = Debugger::new(vm);
+        let mut tui = tui(&mut debugger, console, vec![Key::Char('x'), Key::Ctrl('c')]);
+
+        type_keys(&mut tui, "c");
+        assert!(tui.status.contains("Interrupted"), "{}", tui.status);
+        assert!(tui.keys.next().is_none());
+    }
+
+    #[test]
+    fn test_navigation() {
+        // in r0; halt
+        let console = BufferConsole::new();
+        let mut vm = Vm::with_console(Box::new(console.clone()));
+        vm.load_memory(vec![20, 32768, 0]).unwrap();
+        let mut debugger = Debugger::new(vm);
+        let mut tui = tui(&mut debugger, console, vec![]);
+
+        // stepping onto an `in` with nothing to read asks for a line too
+        type_keys(&mut tui, "s");
+        assert_eq!(Mode::Input(String::new()), tui.mode);
+        tui.key(Key::Esc);
+
+        type_keys(&mut tui, "g0x7ffe\n");
+        assert_eq!(LAST_PAGE, tui.memory_addr);
+        tui.key(Key::PageDown);
+        assert_eq!(LAST_PAGE, tui.memory_addr);
+        type_keys(&mut tui, "g0x8000\n");
+        assert_eq!("Not an address: 0x8000", tui.status);
+
+        tui.selected = LAST_ADDRESS;
+        type_keys(&mut tui, "j");
+        assert_eq!(LAST_ADDRESS, tui.selected);
+    }
+
+    #[test]
+    fn test_pane() {
+        let mut buf = String::new();
+        pane(&mut buf, 2, 3, 10, 4, "Regs", &["hello, world".to_string()]);
+        assert_eq!(format!("{}┌ Regs ──┐{}│hello, w│{}│        │{}└────────┘", Goto(2, 3), Goto(2, 4), Goto(2, 5), Goto(2, 6)), buf);
+
+        // titles are cut short rather than overflowing the border
+        let mut buf = String::new();
+        pane(&mut buf, 1, 1, 6, 2, "Disassembly", &[]);
+        assert_eq!(format!("{}┌ Dis┐{}└────┘", Goto(1, 1), Goto(1, 2)), buf);
+
+        let mut buf = String::new();
+        pane(&mut buf, 1, 1, 3, 10, "Too narrow", &[]);
+        assert_eq!("", buf);
+    }
+}
\ No newline at end of file
@@ -2,14 +2,18 @@
 #![feature(try_from)]
 #![feature(try_trait)]
 
+extern crate ctrlc;
 extern crate getopts;
+#[macro_use] extern crate json;
 #[macro_use] extern crate lazy_static;
 extern crate regex;
+extern crate termion;
 
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
+use std::sync::atomic::Ordering;
 
 use getopts::Options;
 
@@ -28,6 +32,21 @@ fn main() {
     opts.optopt("a", "assemble", "assemble the selected source file into a binary file", "SOURCE");
     opts.optopt("d", "disassemble", "disassemble the selected binary file into a source file", "BINARY");
     opts.optflag("g", "debugger", "attach debugger to program run");
+    opts.optflag("", "tui", "with -g, use the full-screen debugger");
+    opts.optmulti("x", "command", "run the debugger commands in FILE before the first prompt", "FILE");
+    opts.optflag("", "dap", "speak the Debug Adapter Protocol on stdin/stdout");
+    opts.optopt("", "gdb", "serve the program to gdb on localhost PORT instead of running it", "PORT");
+    opts.optopt("", "find", "search BINARY for PATTERN, as the debugger's find command does, instead of running it", "PATTERN");
+    opts.optopt("", "save-state", "save the machine state to FILE when the run stops", "FILE");
+    opts.optopt("", "load-state", "resume from the machine state in FILE instead of loading BINARY", "FILE");
+    opts.optopt("", "break-key", "break into the debugger when KEY (a character, or ^X for a control key) is typed", "KEY");
+    opts.optopt("", "meta-prefix", "treat input lines starting with PREFIX as commands for the VM, not the program (default !; empty to turn off)", "PREFIX");
+    opts.optopt("", "symbols", "load labels from FILE (default: BINARY with a .sym extension, if there is one)", "FILE");
+    opts.optopt("", "lines", "load a line table from FILE (default: BINARY with a .lines extension, if there is one)", "FILE");
+    opts.optflag("", "strict", "trap on anything the arch-spec leaves undefined");
+    opts.optopt("", "trace", "write a JSON Lines execution trace to FILE", "FILE");
+    opts.optmulti("", "trace-range", "only trace instructions between START and END inclusive", "START:END");
+    opts.optmulti("", "trace-op", "only trace instructions with this mnemonic", "OPCODE");
     opts.optflag("h", "help", "prints this help menu");
 
     let matches = match opts.parse(&args[1..]) {
@@ -38,28 +57,159 @@ fn main() {
     if matches.opt_present("h") {
         print_usage(&program, opts);
         return;
+    } else if matches.opt_present("g") && matches.opt_present("gdb") {
+        eprintln!("-g and --gdb can't be used together");
+        std::process::exit(1);
+    } else if matches.opt_present("dap") {
+        if let Err(e) = debugger::dap::serve() {
+            eprintln!("Debug adapter failed: {}", e);
+        }
     } else if matches.opt_present("r") {
         match matches.opt_str("r") {
             Some(filename) => {
-                let mut f = File::open(filename).expect("File not found");
-                let mut challenge: Vec<u8> = Vec::new();
-                match f.read_to_end(&mut challenge) {
-                    Ok(_) => {
-                        //if bytes_read != EXPECTED_PROGRAM_SIZE { panic!("Did not read the complete program, only read {}/{} bytes", bytes_read, EXPECTED_PROGRAM_SIZE); }
-
-                        let program: Vec<synacor::WORD> = challenge.chunks(2).map(|c| ((c[1] as synacor::WORD) << 8) + c[0] as synacor::WORD).collect();
-                        let mut pooter = synacor::Vm::new();
-
-                        if matches.opt_present("g") {
-                            // TODO figure out how to attach debugger, and intercept keypresses
-                            println!("Debugger not yet supported");
-                            pooter.set_debug(true);
+                let mut pooter = match matches.opt_str("load-state") {
+                    Some(state_file) => match synacor::Snapshot::load(&state_file) {
+                        Ok(snapshot) => synacor::Vm::from_snapshot(snapshot),
+                        Err(e) => {
+                            eprintln!("Unable to load snapshot {}: {}", state_file, e);
+                            std::process::exit(1);
+                        },
+                    },
+                    None => {
+                        let mut f = File::open(&filename).expect("File not found");
+                        let mut challenge: Vec<u8> = Vec::new();
+                        match f.read_to_end(&mut challenge) {
+                            Ok(_) => {
+                                //if bytes_read != EXPECTED_PROGRAM_SIZE { panic!("Did not read the complete program, only read {}/{} bytes", bytes_read, EXPECTED_PROGRAM_SIZE); }
+
+                                let program: Vec<synacor::WORD> = challenge.chunks(2).map(|c| ((c[1] as synacor::WORD) << 8) + c[0] as synacor::WORD).collect();
+                                let mut pooter = synacor::Vm::new();
+                                if let Err(e) = pooter.load_memory(program) { panic!("Unable to load challenge program: {}", e); }
+                                pooter
+                            },
+                            Err(e) => panic!("Unable to read challenge program: {}", e)
                         }
+                    },
+                };
 
-                        pooter.load_memory(program);
-                        pooter.run();
+                if let Some(pattern) = matches.opt_str("find") {
+                    let stdout = std::io::stdout();
+                    match debugger::search::parse(&pattern) {
+                        Ok(pattern) => { let _ = debugger::search::report(pooter.memory().words(), &pattern, &mut stdout.lock()); },
+                        Err(e) => println!("{}", e),
+                    }
+                    return;
+                }
+
+                match matches.opt_str("symbols") {
+                    Some(sym_file) => match synacor::Symbols::load(&sym_file) {
+                        Ok(symbols) => pooter.set_symbols(symbols),
+                        Err(e) => panic!("Unable to load symbols {}: {}", sym_file, e),
+                    },
+                    None => if let Ok(symbols) = synacor::Symbols::load(Path::new(&filename).with_extension("sym")) {
+                        pooter.set_symbols(symbols);
                     },
-                    Err(e) => panic!("Unable to read challenge program: {}", e)
+                }
+                match matches.opt_str("lines") {
+                    Some(lines_file) => match synacor::LineTable::load(&lines_file) {
+                        Ok(table) => pooter.set_line_table(table),
+                        Err(e) => panic!("Unable to load line table {}: {}", lines_file, e),
+                    },
+                    None => if let Ok(table) = synacor::LineTable::load(Path::new(&filename).with_extension("lines")) {
+                        pooter.set_line_table(table);
+                    },
+                }
+
+                if matches.opt_present("strict") {
+                    pooter.set_strictness(synacor::Strictness::Strict);
+                }
+
+                if let Some(trace_file) = matches.opt_str("trace") {
+                    let mut tracer = synacor::Tracer::to_file(&trace_file).expect("Unable to create trace file");
+                    for range in matches.opt_strs("trace-range") {
+                        let bounds = range.split(':').map(synacor::parse_number).collect::<Vec<_>>();
+                        match &bounds[..] {
+                            [Some(start), Some(end)] => tracer.add_range(*start, *end),
+                            _ => panic!("Invalid trace range: {}", range),
+                        }
+                    }
+                    for op in matches.opt_strs("trace-op") {
+                        tracer.add_opcode(synacor::opcode::Opcode::try_from(&op[..]).expect(&format!("Unknown opcode: {}", op)[..]));
+                    }
+                    pooter.set_tracer(Some(tracer));
+                }
+
+                // Ctrl-C stops the program at the next instruction and opens the debugger
+                let interrupt = pooter.interrupt_handle();
+                ctrlc::set_handler(move || interrupt.store(true, Ordering::SeqCst)).expect("Unable to install Ctrl-C handler");
+
+                let key = matches.opt_str("break-key").map(|k| parse_key(&k).expect(&format!("Invalid break key: {}", k)[..]));
+                let interrupt = pooter.interrupt_handle();
+                let mut console = synacor::EscapeConsole::new(Box::new(synacor::StdConsole::new()), key, interrupt);
+                let prefix = matches.opt_str("meta-prefix").unwrap_or("!".to_string());
+                let meta = if prefix.is_empty() { None } else { Some(console.intercept(&prefix)) };
+                pooter.set_console(Box::new(console));
+
+                let scripts = matches.opt_strs("x");
+                let pooter = if matches.opt_present("g") {
+                    if matches.opt_present("tui") {
+                        let console = synacor::BufferConsole::new();
+                        pooter.set_console(Box::new(console.clone()));
+                        let mut debugger = debugger::Debugger::new(pooter);
+                        if run_debugger_scripts(&mut debugger, &scripts) {
+                            if let Err(e) = debugger::tui::run(&mut debugger, console) {
+                                println!("Terminal error: {}", e);
+                            }
+                        }
+                        debugger.into_vm()
+                    } else {
+                        let mut debugger = debugger::Debugger::new(pooter);
+                        if let Some(ref meta) = meta { debugger.set_meta_commands(meta.clone()); }
+                        if run_debugger_scripts(&mut debugger, &scripts) { debugger.repl(); }
+                        debugger.into_vm()
+                    }
+                } else if let Some(port) = matches.opt_str("gdb") {
+                    let port = port.parse::<u16>().expect(&format!("Invalid port: {}", port)[..]);
+                    let mut debugger = debugger::Debugger::new(pooter);
+                    if let Err(e) = debugger::gdb::serve(&mut debugger, port) {
+                        println!("gdb connection failed: {}", e);
+                    }
+                    debugger.into_vm()
+                } else {
+                    loop {
+                        match pooter.run() {
+                            Ok(&synacor::cpu::CpuState::Running) => {
+                                // stopped by meta-commands, or by Ctrl-C or the break key if there are none
+                                let mut action = debugger::meta::Action::Debug;
+                                while let Some(line) = meta.as_ref().and_then(|m| m.take()) {
+                                    action = debugger::meta::execute(&mut pooter, &line);
+                                    if action == debugger::meta::Action::Debug { break; }
+                                }
+                                if action == debugger::meta::Action::Resume { continue; }
+
+                                println!("\nInterrupted; `continue` resumes the program");
+                                let mut debugger = debugger::Debugger::new(pooter);
+                                if let Some(ref meta) = meta { debugger.set_meta_commands(meta.clone()); }
+                                if run_debugger_scripts(&mut debugger, &scripts) { debugger.repl(); }
+                                break debugger.into_vm();
+                            },
+                            Ok(_) => break pooter,
+                            // input ran out, say from a file on stdin; the `in` is left to run
+                            Err(synacor::VmError::EndOfInput { .. }) => break pooter,
+                            Err(e) => {
+                                println!("VM error: {}", e);
+                                let _ = pooter.cpu().dump(&mut std::io::stderr());
+                                break pooter;
+                            },
+                        }
+                    }
+                };
+
+                if let Some(state_file) = matches.opt_str("save-state") {
+                    match pooter.snapshot().save(&state_file) {
+                        Ok(_) => println!("Saved machine state to {}", state_file),
+                        Err(e) => println!("Unable to save snapshot {}: {}", state_file, e),
+                    }
                 }
             },
             None => println!("You mut supply a filename to run"),
@@ -72,15 +222,25 @@ fn main() {
                 let mut f = File::open(p).expect("file not found");
                 let mut contents: String = String::new();
                 f.read_to_string(&mut contents);
-                assembler::assemble(contents, p.with_extension("bin").to_str().unwrap());
+                assembler::assemble(contents, &filename, p.with_extension("bin").to_str().unwrap());
             },
             None => println!("You must supply a filename to assemble into a binary"),
         }
     } else if matches.opt_present("d") {
         match matches.opt_str("d") {
             Some(filename) => {
-                // TODO run disassembler on file and produce an assembly output
-                unimplemented!();
+                let p = Path::new(&filename);
+                let mut bytes = Vec::new();
+                File::open(p).expect("file not found").read_to_end(&mut bytes).expect("Unable to read binary");
+                let words = bytes.chunks(2).map(|c| *c.get(1).unwrap_or(&0) as synacor::WORD * 256 + c[0] as synacor::WORD).collect::<Vec<_>>();
+                let len = words.len();
+                let memory = synacor::memory::Memory::from_words(words);
+
+                let symbols = debugger::disasm::generate_labels(&memory, len);
+                let mut f = File::create(p.with_extension("asm")).expect("Unable to create source file");
+                debugger::disasm::write_source(&memory, len, &symbols, &mut f).expect("Unable to write source file");
+                symbols.save(p.with_extension("sym")).expect("Unable to write symbol file");
+                println!("Disassembled {} words to {} with {} labels", len, p.with_extension("asm").display(), symbols.len());
             },
             None => println!("You must supply a filename to disassemble into an assembly file"),
         }
@@ -94,20 +254,795 @@ fn print_usage(program: &str, opts: Options) {
     print!("{}", opts.usage(&brief));
 }
 
+/// Runs the debugger's startup file, if there is one, then each `-x` file.
+/// Returns false if one of them quits.
+fn run_debugger_scripts(debugger: &mut debugger::Debugger, scripts: &[String]) -> bool {
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let rc = Path::new(debugger::RC_FILE);
+    let rc = if rc.exists() { Some(rc.to_str().unwrap().to_string()) } else { None };
+    for script in rc.iter().chain(scripts.iter()) {
+        match debugger.source(script, &mut out) {
+            Ok(debugger::Control::Continue) => {},
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Parses a break key: `^]` style control keys, a single character, or a number.
+fn parse_key(key: &str) -> Option<synacor::WORD> {
+    let chars = key.chars().collect::<Vec<_>>();
+    match &chars[..] {
+        ['^', c] if c.is_ascii() => Some((c.to_ascii_uppercase() as u8 ^ 0x40) as synacor::WORD),
+        [c] if c.is_ascii() => Some(*c as synacor::WORD),
+        _ => synacor::parse_number(key).map(|n| n as synacor::WORD),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use synacor;
 
+    /// Runs a debugger command and returns what it printed.
+    fn run(debugger: &mut ::debugger::Debugger, line: &str) -> String {
+        let mut out = Vec::new();
+        debugger.command(line, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
     #[test]
     fn test_basic_program() {
         let test_program: Vec<synacor::WORD> = vec![9, 32768, 32769, 4, 19, 32768];
         let mut vm = synacor::Vm::new();
-        vm.load_memory(test_program);
-        { vm.run(); }
+        vm.load_memory(test_program).unwrap();
+        { vm.run().unwrap(); }
 
         assert_eq!(&synacor::cpu::CpuState::Halted, vm.cpu().state());
         assert_eq!(0u16, vm.cpu().register_get(1));
         assert_eq!(4u16, vm.cpu().register_get(0));
         assert_eq!(6, vm.cpu().pc());
     }
+
+    #[test]
+    fn test_buffer_console_echo() {
+        // in r0; out r0; in r0; out r0; halt
+        let test_program: Vec<synacor::WORD> = vec![20, 32768, 19, 32768, 20, 32768, 19, 32768, 0];
+        let console = synacor::BufferConsole::new();
+        console.push_input("hi");
+        let mut vm = synacor::Vm::with_console(Box::new(console.clone()));
+        vm.load_memory(test_program).unwrap();
+        vm.run().unwrap();
+
+        assert_eq!(&synacor::cpu::CpuState::Halted, vm.cpu().state());
+        assert_eq!("hi", console.output());
+    }
+
+    #[test]
+    fn test_errors_are_reported() {
+        // mod r0 1 r1 with r1 == 0
+        let mut vm = synacor::Vm::new();
+        vm.set_strictness(synacor::Strictness::Strict);
+        vm.load_memory(vec![11, 32768, 1, 32769]).unwrap();
+        assert_eq!(Err(synacor::VmError::DivideByZero { pc: 0, raw: vec![11, 32768, 1, 32769] }), vm.run().map(|s| *s));
+        assert_eq!(&synacor::cpu::CpuState::Error, vm.cpu().state());
+
+        // operand in the invalid range
+        let mut vm = synacor::Vm::new();
+        vm.load_memory(vec![21, 1, 32768, 0x9000]).unwrap();
+        assert_eq!(Err(synacor::VmError::InvalidOperand { pc: 1, raw: vec![1, 32768, 0x9000], value: 0x9000 }), vm.run().map(|s| *s));
+
+        // the dump is left to the caller
+        let mut dump = Vec::new();
+        vm.cpu().dump(&mut dump).unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+        assert!(dump.ends_with("Last 5 instructions:\n  (Noop, 0, 0, 0)\n  (Set, 32768, 36864, 0)\n"), "{}", dump);
+
+        let mut vm = synacor::Vm::new();
+        vm.load_memory(vec![22]).unwrap();
+        assert_eq!(Err(synacor::VmError::IllegalOpcode { pc: 0, raw: vec![22] }), vm.run().map(|s| *s));
+        assert_eq!(Err(synacor::error::Fault::InvalidOpcode(0xFFFF)), synacor::opcode::Opcode::decode(0xFFFF));
+
+        // ret with an empty stack is a halt, not a fault, however strict
+        for &strictness in [synacor::Strictness::Lenient, synacor::Strictness::Strict].iter() {
+            let mut vm = synacor::Vm::new();
+            vm.set_strictness(strictness);
+            vm.load_memory(vec![21, 18]).unwrap();
+            assert_eq!(Ok(synacor::cpu::CpuState::Halted), vm.run().map(|s| *s));
+            assert_eq!(1, vm.cpu().pc());
+        }
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        // push 7; in r0; out r0; pop r1; halt
+        let console = synacor::BufferConsole::new();
+        let mut vm = synacor::Vm::with_console(Box::new(console.clone()));
+        vm.load_memory(vec![2, 7, 20, 32768, 19, 32768, 3, 32769, 0]).unwrap();
+        assert!(vm.run().is_err()); // no input yet
+
+        let mut bytes: Vec<u8> = Vec::new();
+        vm.snapshot().write_to(&mut bytes).unwrap();
+        let snapshot = synacor::Snapshot::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(vm.snapshot(), snapshot);
+
+        // a corrupt stack length is an error, not a huge allocation
+        let mut corrupt = bytes.clone();
+        let len_at = corrupt.len() - 6;
+        corrupt[len_at..len_at + 4].copy_from_slice(&[0xFF; 4]);
+        assert!(synacor::Snapshot::read_from(&mut &corrupt[..]).is_err());
+
+        let mut restored = synacor::Vm::from_snapshot(snapshot);
+        restored.set_console(Box::new(console.clone()));
+        console.push_input("x");
+        restored.run().unwrap();
+
+        assert_eq!("x", console.output());
+        assert_eq!(7u16, restored.cpu().register_get(1));
+    }
+
+    #[test]
+    fn test_end_of_input() {
+        // in r0; out r0; jmp 0
+        let input = ::std::env::temp_dir().join("synacor_test_eof.txt");
+        ::std::fs::write(&input, "a").unwrap();
+        let console = synacor::FileConsole::open(Some(&input), None).unwrap();
+        let mut vm = synacor::Vm::new();
+        vm.set_console(Box::new(synacor::EscapeConsole::new(Box::new(console), None, vm.interrupt_handle())));
+        vm.load_memory(vec![20, 32768, 19, 32768, 6, 0]).unwrap();
+        ::std::fs::remove_file(&input).unwrap();
+
+        // running out of input isn't the same as having none yet, and
+        // leaves the `in` to run again
+        assert_eq!(Err(synacor::VmError::EndOfInput { pc: 0, raw: vec![20, 32768] }), vm.run().map(|s| *s));
+        assert_eq!(&synacor::cpu::CpuState::Running, vm.cpu().state());
+        assert_eq!(97, vm.cpu().register_get(0));
+
+        let mut vm = synacor::Vm::with_console(Box::new(synacor::BufferConsole::new()));
+        vm.load_memory(vec![20, 32768, 0]).unwrap();
+        assert_eq!(Err(synacor::VmError::InputUnavailable { pc: 0, raw: vec![20, 32768] }), vm.run().map(|s| *s));
+    }
+
+    #[test]
+    fn test_step_back() {
+        // set r0 5; push r0; wmem 100 r0; add r0 r0 1; pop r1; halt
+        let mut vm = synacor::Vm::new();
+        vm.set_undo_depth(100);
+        vm.load_memory(vec![1, 32768, 5, 2, 32768, 16, 100, 32768, 9, 32768, 32768, 1, 3, 32769, 0]).unwrap();
+        vm.run().unwrap();
+        assert_eq!(6u16, vm.cpu().register_get(0));
+        assert_eq!(5u16, vm.cpu().register_get(1));
+
+        assert_eq!(2, vm.step_back(2)); // halt, pop
+        assert_eq!(0u16, vm.cpu().register_get(1));
+        assert_eq!(&synacor::cpu::CpuState::Running, vm.cpu().state());
+
+        assert!(vm.run_back_to(3));
+        assert_eq!(5u16, vm.cpu().register_get(0));
+        assert_eq!(vec![0u16; 0], vm.snapshot().stack);
+        assert_eq!(0u16, vm.snapshot().memory[100]);
+
+        vm.run().unwrap();
+        assert_eq!(5u16, vm.snapshot().memory[100]);
+        assert_eq!(5u16, vm.cpu().register_get(1));
+        assert_eq!(6, vm.step_back(10));
+    }
+
+    /// A writer whose output can still be read once a tracer owns it.
+    #[derive(Clone)]
+    struct SharedBuffer(::std::rc::Rc<::std::cell::RefCell<Vec<u8>>>);
+
+    impl ::std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> { self.0.borrow_mut().write(buf) }
+        fn flush(&mut self) -> ::std::io::Result<()> { Ok(()) }
+    }
+
+    fn trace(configure: &dyn Fn(&mut synacor::Tracer)) -> Vec<::json::JsonValue> {
+        // set r0 5; push r0; wmem 100 r0; pop r1; halt
+        let buffer = SharedBuffer(Default::default());
+        let mut tracer = synacor::Tracer::new(Box::new(buffer.clone()));
+        configure(&mut tracer);
+        let mut vm = synacor::Vm::new();
+        vm.load_memory(vec![1, 32768, 5, 2, 32768, 16, 100, 32768, 3, 32769, 0]).unwrap();
+        let mut symbols = synacor::Symbols::new();
+        symbols.insert("a \"label\"\\", 8);
+        vm.set_symbols(symbols);
+        vm.set_tracer(Some(tracer));
+        vm.run().unwrap();
+
+        let text = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        text.lines().map(|l| ::json::parse(l).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_trace() {
+        let records = trace(&|_| {});
+        assert_eq!(5, records.len());
+        assert_eq!(object!{ "n" => 0, "pc" => 0, "op" => "set", "raw" => array![1, 32768, 5], "args" => array![0, 5],
+                            "reg_writes" => array![object!{ "reg" => 0, "old" => 0, "new" => 5 }],
+                            "mem_writes" => array![], "push" => array![], "pop" => array![] }, records[0]);
+        assert_eq!(array![5], records[1]["push"]);
+        assert_eq!(array![100, 5], records[2]["args"]);
+        assert_eq!(array![object!{ "addr" => 100, "old" => 0, "new" => 5 }], records[2]["mem_writes"]);
+        assert_eq!(array![5], records[3]["pop"]);
+        assert_eq!("a \"label\"\\", records[3]["sym"]);
+        assert_eq!("a \"label\"\\+2", records[4]["sym"]);
+        assert_eq!("halt", records[4]["op"]);
+
+        // ranges and opcodes both have to match
+        let pcs = |records: Vec<::json::JsonValue>| records.iter().map(|r| r["pc"].as_usize().unwrap()).collect::<Vec<_>>();
+        assert_eq!(vec![5], pcs(trace(&|t| t.add_range(5, 7))));
+        assert_eq!(vec![3, 8], pcs(trace(&|t| { t.add_opcode(synacor::opcode::Opcode::Push); t.add_opcode(synacor::opcode::Opcode::Pop); })));
+        assert_eq!(vec![8], pcs(trace(&|t| { t.add_range(6, 10); t.add_opcode(synacor::opcode::Opcode::Push); t.add_opcode(synacor::opcode::Opcode::Pop); })));
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // 0: out 65; wmem 1 66; add r0 r0 1; eq r1 r0 2; jf r1 0; halt
+        let console = synacor::BufferConsole::new();
+        let mut vm = synacor::Vm::with_console(Box::new(console.clone()));
+        vm.load_memory(vec![19, 65, 16, 1, 66, 9, 32768, 32768, 1, 4, 32769, 32768, 2, 8, 32769, 0, 0]).unwrap();
+        vm.run().unwrap();
+
+        assert_eq!("AB", console.output());
+    }
+
+    #[test]
+    fn test_block_engine_matches_interpreter() {
+        let challenge = include_bytes!("../challenge.bin");
+        let program: Vec<synacor::WORD> = challenge.chunks(2).map(|c| ((c[1] as synacor::WORD) << 8) + c[0] as synacor::WORD).collect();
+
+        let mut results = Vec::new();
+        for engine in vec![synacor::Engine::Interpreter, synacor::Engine::Blocks] {
+            let console = synacor::BufferConsole::new();
+            console.push_input("take tablet\nuse tablet\ngo doorway\n");
+            let mut vm = synacor::Vm::with_console(Box::new(console.clone()));
+            vm.set_engine(engine);
+            vm.load_memory(program.clone()).unwrap();
+            assert!(vm.run().is_err()); // runs out of input
+
+            results.push((console.output(), vm.snapshot(), vm.instruction_count(), vm.cpu().last_instruction()));
+        }
+
+        assert_eq!(results[0], results[1]);
+
+        // instructions run inside a block still make it into the history
+        let mut vm = synacor::Vm::new();
+        vm.set_engine(synacor::Engine::Blocks);
+        vm.load_memory(vec![9, 32768, 32768, 1, 0]).unwrap();
+        vm.run().unwrap();
+        assert_eq!(Some((synacor::opcode::Opcode::Halt, 0, 0, 0)), vm.cpu().last_instruction());
+    }
+
+    #[test]
+    fn test_bounded_runs() {
+        use synacor::{Event,StopReason};
+
+        // 0: add r0 r0 1; out 65; jmp 0
+        let console = synacor::BufferConsole::new();
+        let mut vm = synacor::Vm::with_console(Box::new(console.clone()));
+        vm.load_memory(vec![9, 32768, 32768, 1, 19, 65, 6, 0]).unwrap();
+
+        assert_eq!(StopReason::BudgetExhausted, vm.run_for(7));
+        assert_eq!(3u16, vm.cpu().register_get(0));
+        assert_eq!(4, vm.cpu().pc());
+
+        assert_eq!(StopReason::BreakpointHit, vm.run_until(6usize));
+        assert_eq!(6, vm.cpu().pc());
+        assert_eq!(StopReason::BreakpointHit, vm.run_until(6usize));
+        assert_eq!(4u16, vm.cpu().register_get(0));
+
+        let reason = vm.run_until(|e: &Event| match *e {
+            Event::Executed { effects, .. } => effects.iter().any(|f| *f == synacor::effect::Effect::RegisterWrite { reg: 0, old: 9, new: 10 }),
+            _ => false,
+        });
+        assert_eq!(StopReason::BreakpointHit, reason);
+        assert_eq!(10u16, vm.cpu().register_get(0));
+        assert_eq!("AAAAAAAAA", console.output());
+
+        // in r0; halt
+        let console = synacor::BufferConsole::new();
+        let mut vm = synacor::Vm::with_console(Box::new(console.clone()));
+        vm.load_memory(vec![20, 32768, 0]).unwrap();
+        assert_eq!(StopReason::WaitingForInput, vm.run_for(10));
+        console.push_input("z");
+        assert_eq!(StopReason::Halted, vm.run_for(10));
+        assert_eq!(122u16, vm.cpu().register_get(0));
+    }
+
+    #[test]
+    fn test_backtrace() {
+        // 0: call 4; halt; 4: push 9; call 10; 8: push 5; 10: ret
+        let mut vm = synacor::Vm::new();
+        vm.set_undo_depth(10);
+        vm.load_memory(vec![17, 4, 0, 0, 2, 9, 17, 10, 2, 5, 18]).unwrap();
+        assert_eq!(synacor::StopReason::BreakpointHit, vm.run_until(10usize));
+
+        let frames = vm.backtrace();
+        assert_eq!(2, frames.len());
+        assert_eq!(synacor::call_stack::Frame { caller: 6, callee: 10, depth: 2 }, frames[0]);
+        assert_eq!(synacor::call_stack::Frame { caller: 0, callee: 4, depth: 0 }, frames[1]);
+
+        vm.step().unwrap();
+        assert_eq!(1, vm.backtrace().len());
+        vm.step_back(1);
+        assert_eq!(2, vm.backtrace().len());
+        vm.step().unwrap();
+
+        // the next ret pops 5 rather than the outer return address
+        vm.run_until(10usize);
+        vm.step().unwrap();
+        assert_eq!(1, vm.backtrace().len());
+        assert_eq!(1, vm.cpu().call_stack().mismatches().len());
+        assert_eq!(Some(2), vm.cpu().call_stack().mismatches()[0].expected);
+
+        // a callee that drops its return address and returns elsewhere still
+        // closes its frame
+        // 0: call 4; halt; halt; 4: pop r0; push 3; ret
+        let mut vm = synacor::Vm::new();
+        vm.load_memory(vec![17, 4, 0, 0, 3, 32768, 2, 3, 18]).unwrap();
+        vm.run().unwrap();
+        assert_eq!(3, vm.cpu().pc());
+        assert!(vm.backtrace().is_empty());
+        assert_eq!(Some(2), vm.cpu().call_stack().mismatches()[0].expected);
+
+        // a restored snapshot has no frames to check a ret against
+        // 0: push 3; ret; halt
+        let mut vm = synacor::Vm::new();
+        vm.load_memory(vec![2, 3, 18, 0]).unwrap();
+        vm.run_until(2usize);
+        let mut vm = synacor::Vm::from_snapshot(vm.snapshot());
+        vm.run().unwrap();
+        assert_eq!(3, vm.cpu().pc());
+        assert!(vm.cpu().call_stack().mismatches().is_empty());
+    }
+
+    #[test]
+    fn test_debugger_commands() {
+        // set r0 5; out 65; out 66; halt
+        let console = synacor::BufferConsole::new();
+        let mut vm = synacor::Vm::with_console(Box::new(console.clone()));
+        vm.load_memory(vec![1, 32768, 5, 19, 65, 19, 66, 0]).unwrap();
+        let mut debugger = ::debugger::Debugger::new(vm);
+
+        run(&mut debugger, "b 3");
+        let out = run(&mut debugger, "c");
+        assert!(out.contains("Breakpoint 1 at 0x0003"), "{}", out);
+        assert_eq!("", console.output());
+        let out = run(&mut debugger, "s");
+        assert!(out.contains("0x0005"), "{}", out);
+        assert_eq!("A", console.output());
+
+        assert_eq!(concat!("0x0000: 0001 8000 0005 0013 0041 0013 0042 0000  |....A.B.|\n",
+                           "0x0008: 0000 0000                                |..|\n"), run(&mut debugger, "x/10 0"));
+        assert_eq!("0xFFFF: 0000                                     |.|\n", run(&mut debugger, "x/99999999999 0xFFFF"));
+        assert_eq!("Usage: x/N ADDR\n", run(&mut debugger, "x/4 0xFFFFFFFFFFFFFFFF"));
+        assert_eq!("Unknown command 'xyz', try 'help'\n", run(&mut debugger, "xyz 0"));
+    }
+
+    #[test]
+    fn test_strictness() {
+        // rmem r0 14; add r1 r0 r0; mod r2 7 r3; halt; 14: 0xFFFF
+        let program = vec![15, 32768, 14, 9, 32769, 32768, 32768, 11, 32770, 7, 32771, 0, 0, 0, 0xFFFF];
+
+        let mut vm = synacor::Vm::new();
+        vm.load_memory(program.clone()).unwrap();
+        vm.run().unwrap();
+        assert_eq!(0x7FFEu16, vm.cpu().register_get(1));
+        assert_eq!(7u16, vm.cpu().register_get(2));
+
+        let mut vm = synacor::Vm::new();
+        vm.set_strictness(synacor::Strictness::Strict);
+        vm.load_memory(program.clone()).unwrap();
+        assert_eq!(Err(synacor::VmError::InvalidValue { pc: 0, raw: vec![15, 32768, 14], value: 0xFFFF }), vm.run().map(|s| *s));
+
+        let mut vm = synacor::Vm::new();
+        vm.set_strictness(synacor::Strictness::Strict);
+        vm.load_memory(program[7..].to_vec()).unwrap();
+        assert_eq!(Err(synacor::VmError::DivideByZero { pc: 0, raw: vec![11, 32770, 7, 32771] }), vm.run().map(|s| *s));
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        // set r1 5; rmem r0 10; wmem 11 r1; halt; 10: 42; 11: 0
+        let program = vec![1, 32769, 5, 15, 32768, 10, 16, 11, 32769, 0, 42, 0];
+        let mut vm = synacor::Vm::with_console(Box::new(synacor::BufferConsole::new()));
+        vm.load_memory(program).unwrap();
+        let mut debugger = ::debugger::Debugger::new(vm);
+
+        run(&mut debugger, "watch r1");
+        run(&mut debugger, "rwatch 10");
+        run(&mut debugger, "watch [11]");
+
+        let out = run(&mut debugger, "c");
+        assert!(out.contains("Watchpoint 1 (write to r1): 0x0000 -> 0x0005"), out);
+        assert!(out.contains("by 0x0000: set r1 #5"), out);
+        assert!(run(&mut debugger, "c").contains("Watchpoint 2 (read of [0x000A]): value 0x002A"));
+        assert!(run(&mut debugger, "c").contains("Watchpoint 3 (write to [0x000B]): 0x0000 -> 0x0005"));
+        assert!(run(&mut debugger, "c").contains("Program halted"));
+
+        // wmem 12 r0; wmem 15 r0; halt
+        let program = vec![16, 12, 32768, 16, 15, 32768, 0];
+        let mut vm = synacor::Vm::with_console(Box::new(synacor::BufferConsole::new()));
+        vm.load_memory(program).unwrap();
+        let mut debugger = ::debugger::Debugger::new(vm);
+
+        assert_eq!("Watchpoint 1 (write to [0x000D..0x0010])\n", run(&mut debugger, "watch 13 16"));
+        assert_eq!("Usage: watch rN|ADDR [END] [if EXPR]\n", run(&mut debugger, "watch 16 13"));
+        assert_eq!("Usage: watch rN|ADDR [END] [if EXPR]\n", run(&mut debugger, "watch r0 16"));
+        let out = run(&mut debugger, "c");
+        assert!(out.contains("Watchpoint 1 (write to [0x000D..0x0010]): [0x000F] 0x0000 -> 0x0000"), "{}", out);
+        assert!(out.contains("by 0x0003"), "{}", out);
+    }
+
+    #[test]
+    fn test_conditional_breakpoints() {
+        // loop: add r0 r0 1; eq r1 r0 10; jf r1 loop; halt
+        let program = vec![9, 32768, 32768, 1, 4, 32769, 32768, 10, 8, 32769, 0, 0];
+        let mut vm = synacor::Vm::with_console(Box::new(synacor::BufferConsole::new()));
+        vm.load_memory(program).unwrap();
+        let mut debugger = ::debugger::Debugger::new(vm);
+
+        assert_eq!("Error: unexpected end of expression\n", run(&mut debugger, "b 4 if r0 =="));
+        run(&mut debugger, "b 4 if r0 == 7 && stack.depth == 0");
+        run(&mut debugger, "b 8");
+        run(&mut debugger, "ignore 2 2");
+        assert_eq!("1: r0 * 2 = 0 (0x0000)\n", run(&mut debugger, "display r0 * 2"));
+
+        let out = run(&mut debugger, "c");
+        assert!(out.contains("Breakpoint 2 at 0x0008"), out);
+        assert!(out.contains("1: r0 * 2 = 6 (0x0006)"), out);
+
+        run(&mut debugger, "delete 2");
+        let out = run(&mut debugger, "c");
+        assert!(out.contains("Breakpoint 1 at 0x0004"), out);
+        assert_eq!("7 (0x0007)\n", run(&mut debugger, "p r0"));
+        assert!(run(&mut debugger, "bl").contains("break at 0x0004 if r0 == 7 && stack.depth == 0, hit 1 times"));
+        assert!(run(&mut debugger, "c").contains("Program halted"));
+    }
+
+    #[test]
+    fn test_interrupts() {
+        use std::sync::atomic::Ordering;
+
+        // in r0; out r0; jmp 0
+        let test_program: Vec<synacor::WORD> = vec![20, 32768, 19, 32768, 6, 0];
+        let console = synacor::BufferConsole::new();
+        console.push_input("ab\x1dc");
+        let mut vm = synacor::Vm::new();
+        let interrupt = vm.interrupt_handle();
+        vm.set_console(Box::new(synacor::EscapeConsole::new(Box::new(console.clone()), Some(0x1D), interrupt.clone())));
+        vm.load_memory(test_program).unwrap();
+
+        // the escape key stops the run before `in`, without reaching the program
+        assert_eq!(&synacor::cpu::CpuState::Running, vm.run().unwrap());
+        assert_eq!("ab", console.output());
+        assert_eq!(0, vm.cpu().pc());
+
+        // a bounded run notices the flag at the next instruction boundary
+        interrupt.store(true, Ordering::SeqCst);
+        assert_eq!(synacor::StopReason::Interrupted, vm.run_for(100));
+        assert_eq!(0, vm.cpu().pc());
+
+        // and resumes as if nothing happened
+        assert_eq!(synacor::StopReason::WaitingForInput, vm.run_for(100));
+        assert_eq!("abc", console.output());
+    }
+
+    #[test]
+    fn test_debugger_scripts() {
+        // loop: add r0 r0 1; eq r1 r0 10; jf r1 loop; halt
+        let program = vec![9, 32768, 32768, 1, 4, 32769, 32768, 10, 8, 32769, 0, 0];
+        let mut vm = synacor::Vm::with_console(Box::new(synacor::BufferConsole::new()));
+        vm.load_memory(program).unwrap();
+        let mut debugger = ::debugger::Debugger::new(vm);
+
+        let script = ::std::env::temp_dir().join("synacor_test_script.dbg");
+        ::std::fs::write(&script, "\
+# bump r0 at every pass through the loop
+define bump
+  set r0 r0 + $arg0
+end
+
+break 8
+commands
+  bump 2
+  continue
+end
+").unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(::debugger::Control::Continue, debugger.source(&script, &mut out).unwrap());
+        ::std::fs::remove_file(&script).unwrap();
+        assert_eq!("Breakpoint 1 at 0x0008\n", String::from_utf8(out).unwrap());
+
+        assert!(run(&mut debugger, "c").ends_with("Program halted\n=> 0x000B: halt\n"));
+        assert_eq!("12 (0x000C)\n", run(&mut debugger, "p r0"));
+        assert_eq!("  1  break at 0x0008, hit 4 times\n        bump 2\n        continue\n", run(&mut debugger, "bl"));
+        assert_eq!("Can't set 'r9'\n", run(&mut debugger, "set r9 1"));
+    }
+
+    #[test]
+    fn test_next_and_finish() {
+        let program = vec![
+            17, 6,                  // 0: call 6
+            1, 32769, 1,            // 2: set r1 1
+            0,                      // 5: halt
+            2, 32768,               // 6: push r0
+            17, 13,                 // 8: call 13
+            3, 32768,               // 10: pop r0
+            18,                     // 12: ret
+            9, 32768, 32768, 1,     // 13: add r0 r0 1
+            18,                     // 17: ret
+        ];
+        let mut vm = synacor::Vm::with_console(Box::new(synacor::BufferConsole::new()));
+        vm.load_memory(program).unwrap();
+        let mut debugger = ::debugger::Debugger::new(vm);
+
+        assert_eq!("Not inside a call\n", run(&mut debugger, "finish"));
+        assert_eq!("=> 0x0006: push r0\n", run(&mut debugger, "s"));
+        assert_eq!("=> 0x0008: call #13\n", run(&mut debugger, "s"));
+        assert_eq!("=> 0x000A: pop r0\n", run(&mut debugger, "next"));
+        assert_eq!("Run till exit from 0x0006\n=> 0x0002: set r1 #1\n", run(&mut debugger, "finish"));
+        assert_eq!("0 (0x0000)\n", run(&mut debugger, "p r0")); // restored by the pop
+
+        run(&mut debugger, "rs 7");
+        assert_eq!("=> 0x0002: set r1 #1\n", run(&mut debugger, "n"));
+
+        // a watchpoint that lets the run carry on still leaves the ret's
+        // fetch to be seen, by a breakpoint there and by finish
+        run(&mut debugger, "rs 7");
+        run(&mut debugger, "b 12");
+        run(&mut debugger, "watch r0 if r0 == 99");
+        assert_eq!("Breakpoint 1 at 0x000C\n=> 0x000C: ret\n", run(&mut debugger, "c"));
+        run(&mut debugger, "d 1");
+        run(&mut debugger, "rs 1");
+        assert_eq!("Run till exit from 0x0006\n=> 0x0002: set r1 #1\n", run(&mut debugger, "finish"));
+        run(&mut debugger, "d 2");
+
+        // a ret that closes both frames at once still ends the finish
+        // 0: call 4; halt; halt; 4: call 8; halt; halt; 8: pop r0; ret
+        let mut vm = synacor::Vm::with_console(Box::new(synacor::BufferConsole::new()));
+        vm.load_memory(vec![17, 4, 0, 0, 17, 8, 0, 0, 3, 32768, 18]).unwrap();
+        let mut debugger = ::debugger::Debugger::new(vm);
+        run(&mut debugger, "b 8");
+        run(&mut debugger, "c");
+        assert_eq!("Run till exit from 0x0008\n=> 0x0002: halt\n", run(&mut debugger, "finish"));
+    }
+
+    #[test]
+    fn test_tracepoints() {
+        // loop: add r0 r0 1; eq r1 r0 10; jf r1 loop; halt
+        let program = vec![9, 32768, 32768, 1, 4, 32769, 32768, 10, 8, 32769, 0, 0];
+        let mut vm = synacor::Vm::with_console(Box::new(synacor::BufferConsole::new()));
+        vm.load_memory(program).unwrap();
+        let mut debugger = ::debugger::Debugger::new(vm);
+
+        assert_eq!("Error: '{' without a closing '}'\n", run(&mut debugger, "trace 4 \"r0={r0\""));
+        assert_eq!("Usage: trace ADDR \"FORMAT\" [if EXPR]\n", run(&mut debugger, "trace 4 é\"x\""));
+        assert_eq!("Tracepoint 1 at 0x0004\n", run(&mut debugger, "trace 4 \"r0={r0} {{{r0 * 2:x}}} {65 + r0:c}\" if r0 > 7"));
+        assert_eq!("  1  trace at 0x0004 \"r0={r0} {{{r0 * 2:x}}} {65 + r0:c}\" if r0 > 7\n", run(&mut debugger, "bl"));
+
+        // tracepoints never stop the program; their messages come out at the next stop
+        assert_eq!("r0=8 {0x0010} I\nr0=9 {0x0012} J\nr0=10 {0x0014} K\nProgram halted\n=> 0x000B: halt\n", run(&mut debugger, "c"));
+    }
+
+    #[test]
+    fn test_memory_search() {
+        use debugger::search::{self,Pattern};
+
+        let mut words = vec![3, 'c' as u16, 'a' as u16, 't' as u16, 17, 6, 32768, 'c' as u16, 'a' as u16, 't' as u16, 2, 'a' as u16];
+        assert_eq!(Ok(Pattern::Text("cat".to_string())), search::parse(" \"cat\" "));
+        assert_eq!(Ok(Pattern::Words(vec![Some(17), None, Some(0x8000)])), search::parse("17 ? 0x8000"));
+        assert_eq!(Ok(Pattern::Prefixed("at".to_string())), search::parse("pstr \"at\""));
+        assert!(search::parse("17 x").is_err());
+        assert!(search::parse("pstr").is_err());
+
+        assert_eq!(vec![1, 7], search::search(&words, &search::parse("\"cat\"").unwrap()));
+        assert_eq!(vec![4], search::search(&words, &search::parse("17 ? 0x8000").unwrap()));
+        assert_eq!(vec![3, 9], search::search(&words, &search::parse("116 ?").unwrap()));
+        // the string at the end runs off the end of memory until it gets its second character
+        assert_eq!(vec![0], search::search(&words, &search::parse("pstr \"a\"").unwrap()));
+        words.push('b' as u16);
+        assert_eq!(vec![0, 10], search::search(&words, &search::parse("pstr \"a\"").unwrap()));
+
+        let mut out = Vec::new();
+        search::report(&words, &search::parse("pstr \"a\"").unwrap(), &mut out).unwrap();
+        assert_eq!("0x0000: \"cat\"\n0x000A: \"ab\"\n2 matches\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_write_provenance() {
+        let program = vec![
+            9, 32768, 32768, 1,     // 0: add r0 r0 1
+            16, 100, 32768,         // 4: wmem 100 r0
+            4, 32769, 32768, 3,     // 7: eq r1 r0 3
+            8, 32769, 0,            // 11: jf r1 0
+            0,                      // 14: halt
+        ];
+        let mut vm = synacor::Vm::with_console(Box::new(synacor::BufferConsole::new()));
+        vm.load_memory(program).unwrap();
+        let mut debugger = ::debugger::Debugger::new(vm);
+
+        assert_eq!("Write tracking is off; `track on` starts it\n", run(&mut debugger, "who-wrote r0"));
+        assert_eq!("Write tracking is on\n", run(&mut debugger, "track on"));
+        run(&mut debugger, "track 100");
+        run(&mut debugger, "c");
+        assert_eq!("r0 = 0x0003\n    0x0002 -> 0x0003 at instruction 8 by 0x0000: add r0 r0 #1\n", run(&mut debugger, "who-wrote r0"));
+        assert_eq!("[0x0064] = 0x0003\n\
+                    \x20   0x0000 -> 0x0001 at instruction 1 by 0x0004: wmem #100 r0\n\
+                    \x20   0x0001 -> 0x0002 at instruction 5 by 0x0004: wmem #100 r0\n\
+                    \x20   0x0002 -> 0x0003 at instruction 9 by 0x0004: wmem #100 r0\n", run(&mut debugger, "who-wrote [100]"));
+        assert_eq!("[0x0065] = 0x0000\n    no write recorded\n", run(&mut debugger, "who-wrote 101"));
+
+        // undone writes are forgotten
+        run(&mut debugger, "rs 5");
+        assert_eq!("r0 = 0x0002\n    no write recorded\n", run(&mut debugger, "who-wrote r0"));
+        assert_eq!(2, run(&mut debugger, "who-wrote 100").lines().count() - 1);
+
+        run(&mut debugger, "set r0 7");
+        assert_eq!("r0 = 0x0007\n    0x0002 -> 0x0007 at instruction 8 by the debugger\n", run(&mut debugger, "who-wrote r0"));
+
+        assert_eq!("Write tracking is off\n", run(&mut debugger, "track off"));
+        assert_eq!("Write tracking is off; `track on` starts it\n", run(&mut debugger, "who-wrote r0"));
+        assert_eq!("Write tracking is off; `track on` starts it\n", run(&mut debugger, "track 100"));
+        assert_eq!("Write tracking is on\n", run(&mut debugger, "track on"));
+        assert_eq!("r0 = 0x0007\n    no write recorded\n", run(&mut debugger, "who-wrote r0"));
+    }
+
+    #[test]
+    fn test_meta_commands() {
+        use debugger::meta::{self,Action};
+
+        // in r0; out r0; jmp 0
+        let test_program: Vec<synacor::WORD> = vec![20, 32768, 19, 32768, 6, 0];
+        let console = synacor::BufferConsole::new();
+        console.push_input("hi\n!set r1 = 5\n!dbg\nok\n");
+        let mut vm = synacor::Vm::new();
+        let mut escape = synacor::EscapeConsole::new(Box::new(console.clone()), None, vm.interrupt_handle());
+        let commands = escape.intercept("!");
+        vm.set_console(Box::new(escape));
+        vm.load_memory(test_program).unwrap();
+
+        // each meta-command stops the program before it gets the line
+        assert_eq!(&synacor::cpu::CpuState::Running, vm.run().unwrap());
+        assert_eq!("hi\n", console.output());
+        assert_eq!(Some("set r1 = 5".to_string()), commands.take());
+        assert_eq!(None, commands.take());
+        assert_eq!(Action::Resume, meta::execute(&mut vm, "set r1 = 5"));
+        assert_eq!(5, vm.cpu().register_get(1));
+
+        assert_eq!(&synacor::cpu::CpuState::Running, vm.run().unwrap());
+        assert_eq!(Action::Debug, meta::execute(&mut vm, &commands.take().unwrap()));
+
+        assert!(vm.run().is_err()); // runs out of input
+        assert_eq!("hi\nok\n", console.output());
+    }
+
+    #[test]
+    fn test_symbols() {
+        let symbols = synacor::Symbols::parse("; labels\n0x0006 print_word\n\n13 bump\n").unwrap();
+        assert_eq!(Some(6), symbols.address("print_word"));
+        assert_eq!(Some(15), symbols.resolve("bump+2"));
+        assert_eq!(Some(0x10), symbols.resolve("print_word+0xA"));
+        assert_eq!(None, symbols.describe(5));
+        assert_eq!(Some("print_word+4".to_string()), symbols.describe(10));
+        assert_eq!(Some("bump".to_string()), symbols.describe(13));
+        assert!(synacor::Symbols::parse("print_word 0x0006").is_err());
+
+        // moving a label takes it off its old address
+        let mut moved = synacor::Symbols::new();
+        moved.insert("a", 1);
+        moved.insert("b", 1);
+        moved.insert("a", 5);
+        assert_eq!((Some("b"), Some("a")), (moved.name(1), moved.name(5)));
+        moved.insert("b", 6);
+        assert_eq!(None, moved.name(1));
+        assert_eq!(2, moved.len());
+
+        // the program from test_next_and_finish
+        let program = vec![17, 6, 1, 32769, 1, 0, 2, 32768, 17, 13, 3, 32768, 18, 9, 32768, 32768, 1, 18];
+        let memory = synacor::memory::Memory::from_words(program.clone());
+        let generated = ::debugger::disasm::generate_labels(&memory, program.len());
+        assert_eq!(vec![("sub_0006", 6), ("sub_000D", 13)], generated.iter().collect::<Vec<_>>());
+
+        let mut vm = synacor::Vm::with_console(Box::new(synacor::BufferConsole::new()));
+        vm.load_memory(program).unwrap();
+        vm.set_symbols(symbols);
+        let mut debugger = ::debugger::Debugger::new(vm);
+
+        assert_eq!("Breakpoint 1 at 0x0008 <print_word+2>\n", run(&mut debugger, "b print_word+2"));
+        assert_eq!("Usage: break ADDR|LABEL[+N]|FILE:LINE [if EXPR]\n", run(&mut debugger, "b nowhere"));
+        assert_eq!("Breakpoint 1 at 0x0008 <print_word+2>\n=> 0x0008 <print_word+2>: call #13 <bump>\n", run(&mut debugger, "c"));
+        assert_eq!("#0  0x0008 <print_word+2>\n#1  0x0000  (called 0x0006 <print_word> at stack depth 0)\n", run(&mut debugger, "bt"));
+        assert_eq!("bump:\n   0x000D: add r0 r0 #1\n", run(&mut debugger, "disas bump 1"));
+        assert_eq!("1 (0x0001)\n", run(&mut debugger, "p pc == print_word + 2"));
+        assert_eq!("Error: no symbol 'nowhere'\n", run(&mut debugger, "p nowhere"));
+        assert_eq!("0x000E is bump+1\n", run(&mut debugger, "sym 14"));
+    }
+
+    #[test]
+    fn test_disassembly_round_trip() {
+        let program = vec![
+            6, 5,                   // 0: jmp 5
+            9,                      // 2: data
+            40000,                  // 3: data
+            9,                      // 4: add, cut short by the label at 5
+            1, 32768, 36864,        // 5: set r0 with an invalid operand
+            19, 65,                 // 8: out 'A'
+            7, 32768, 5,            // 10: jt r0 5
+            17, 16,                 // 13: call 16
+            0,                      // 15: halt
+            18,                     // 16: ret
+            9, 32768,               // 17: add, cut short by the end
+        ];
+        let memory = synacor::memory::Memory::from_words(program.clone());
+        let symbols = ::debugger::disasm::generate_labels(&memory, program.len());
+        let mut source = Vec::new();
+        ::debugger::disasm::write_source(&memory, program.len(), &symbols, &mut source).unwrap();
+        let source = String::from_utf8(source).unwrap();
+        assert!(source.contains("loc_0005:\n    set r0 #36864"), "{}", source);
+        assert!(source.contains("    out #65                          ; 0x0008 'A'\n"), "{}", source);
+        assert!(source.contains("    jt r0 loc_0005"), "{}", source);
+
+        let assembly = ::assembler::assemble_program(&source, "round_trip.asm").unwrap();
+        assert_eq!(program, assembly.words);
+        assert_eq!(Some(&16), assembly.labels.get("sub_0010"));
+    }
+
+    #[test]
+    fn test_line_tables() {
+        let source = ::std::env::temp_dir().join("synacor_test_lines.asm");
+        let text = "call sub\nhalt\n\nsub:\n    set r0 #1\n    ret\n";
+        ::std::fs::write(&source, text).unwrap();
+        let mut assembly = ::assembler::assemble_program(text, source.to_str().unwrap()).unwrap();
+        assert_eq!(Some(("synacor_test_lines.asm", 5)), assembly.lines.location(4));
+        assembly.lines.resolve_files(&::std::env::temp_dir());
+        assert_eq!(Err("Line #1: undefined label 'nowhere'".to_string()), ::assembler::assemble_program("jmp nowhere\n", "x.asm").map(|_| ()));
+        assert_eq!(Err("Unable to parse line #2: bogus r0\nUnable to parse line #3: dw 1,x".to_string()),
+                   ::assembler::assemble_program("halt\nbogus r0\ndw 1,x\n", "x.asm").map(|_| ()));
+        assert_eq!(Some((source.to_str().unwrap(), 5)), assembly.lines.location(4));
+        assert_eq!(Some((3, 5)), assembly.lines.address_of("synacor_test_lines.asm", 3));
+        assert!(assembly.lines.starts_line(6) && !assembly.lines.starts_line(4));
+
+        // a saved table names files relative to itself
+        let table_file = ::std::env::temp_dir().join("synacor_test_lines.lines");
+        let mut table = synacor::LineTable::new();
+        table.add(0, 2, "synacor_test_lines.asm", 1);
+        table.save(&table_file).unwrap();
+        let loaded = synacor::LineTable::load(&table_file).unwrap();
+        ::std::fs::remove_file(&table_file).unwrap();
+        assert_eq!(Some((source.to_str().unwrap(), 1)), loaded.location(1));
+        assert!(synacor::LineTable::parse("0x0000 2 1").is_err());
+        assert!(synacor::LineTable::parse("file a.asm\n0x0000 2 0").is_err());
+
+        let mut vm = synacor::Vm::with_console(Box::new(synacor::BufferConsole::new()));
+        vm.load_memory(assembly.words).unwrap();
+        vm.set_symbols(synacor::Symbols::from_labels(&assembly.labels));
+        vm.set_line_table(assembly.lines);
+        let mut debugger = ::debugger::Debugger::new(vm);
+        ::std::fs::remove_file(&source).unwrap();
+
+        assert_eq!("Breakpoint 1 at 0x0003 <sub>: synacor_test_lines.asm:5\n", run(&mut debugger, "b synacor_test_lines.asm:3"));
+        run(&mut debugger, "d 1");
+        assert_eq!("=> 0x0003 <sub>: set r0 #1\nsynacor_test_lines.asm:5  set r0 #1\n", run(&mut debugger, "s"));
+        assert_eq!("#0  0x0003 <sub> at synacor_test_lines.asm:5\n#1  0x0000 at synacor_test_lines.asm:1  (called 0x0003 <sub> at stack depth 0)\n", run(&mut debugger, "bt"));
+        run(&mut debugger, "rs");
+        assert_eq!("=> 0x0002: halt\nsynacor_test_lines.asm:2  halt\n", run(&mut debugger, "n"));
+        run(&mut debugger, "rs 3");
+        assert_eq!("=> 0x0003 <sub>: set r0 #1\nsynacor_test_lines.asm:5  set r0 #1\n", run(&mut debugger, "si"));
+        run(&mut debugger, "watch r0 if r0 == 99");
+        assert_eq!("=> 0x0006 <sub+3>: ret\nsynacor_test_lines.asm:6  ret\n", run(&mut debugger, "s"));
+        run(&mut debugger, "d 2");
+        run(&mut debugger, "rs");
+        assert_eq!(run(&mut debugger, "si 0"), run(&mut debugger, "s 0"));
+        assert_eq!(run(&mut debugger, "si 0"), run(&mut debugger, "n 0"));
+
+        // with no line table, `next N` steps over N instructions
+        debugger.vm_mut().set_line_table(synacor::LineTable::new());
+        run(&mut debugger, "rs");
+        assert_eq!("=> 0x0002: halt\n", run(&mut debugger, "n 1"));
+        run(&mut debugger, "rs");
+        assert_eq!("Program halted\n=> 0x0002: halt\n", run(&mut debugger, "n 2"));
+    }
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
 use super::WORD;
+use super::error::Fault;
 
 #[derive(Debug)]
 pub enum Addr {
@@ -7,11 +8,11 @@ pub enum Addr {
 }
 
 impl Addr {
-    pub fn map(value: WORD) -> Addr {
+    pub fn map(value: WORD) -> Result<Addr,Fault> {
         match value {
-            v if v <  0x8000               => Addr::Immediate(v),
-            v if v >= 0x8000 && v < 0x8008 => Addr::Register((v - 0x8000) as usize),
-            v                              => panic!("Invalid number: {}", v),
+            v if v <  0x8000               => Ok(Addr::Immediate(v)),
+            v if v >= 0x8000 && v < 0x8008 => Ok(Addr::Register((v - 0x8000) as usize)),
+            v                              => Err(Fault::InvalidOperand(v)),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,292 @@
+//! Alternative execution engine that translates straight-line runs of
+//! instructions into chains of closures.
+//!
+//! A block starts at whatever address execution reaches and runs until the
+//! first control-flow instruction (`jmp`, `jt`, `jf`, `call`, `ret`, `halt`),
+//! which is included. `in`, and anything the interpreter would fault on,
+//! also ends the block but is left for the interpreter to execute, so errors
+//! and input handling behave exactly as they do under `Cpu::step`.
+//!
+//! Compiled operations follow the lenient rules only; strict machines are
+//! always interpreted.
+
+use std::collections::HashMap;
+use std::ops::{BitAnd,BitOr};
+use super::WORD;
+use super::addr::Addr;
+use super::bus::Bus;
+use super::call_stack::Frame;
+use super::console::Console;
+use super::cpu::{Cpu,CpuState,Instruction,MAX_MEM_ADDR,MODULO};
+use super::opcode::Opcode;
+
+/// Longest block we will build, to keep a runaway straight-line region of
+/// data-that-decodes-as-code from producing huge blocks.
+const MAX_BLOCK_LEN: usize = 256;
+
+#[derive(Clone,Copy)]
+enum Operand {
+    Reg(usize),
+    Imm(WORD),
+}
+
+impl Operand {
+    fn get(&self, cpu: &Cpu) -> WORD {
+        match *self {
+            Operand::Reg(r) => cpu.register_get(r),
+            Operand::Imm(i) => i,
+        }
+    }
+}
+
+/// What happened when a compiled instruction ran.
+enum Flow {
+    Next,
+    Jump(usize),
+    Halt,
+    /// Memory was written; the block must stop if it overwrote code.
+    Wrote(usize),
+    /// The instruction would fault, and was not executed.
+    Fallback,
+}
+
+type Op = Box<dyn Fn(&mut Cpu, &mut Bus, &mut dyn Console) -> Flow>;
+
+struct Block {
+    // (address, address of the following instruction, decoded instruction, operation)
+    ops: Vec<(usize, usize, Instruction, Op)>,
+}
+
+/// How a call to `BlockEngine::run_block` ended.
+pub enum BlockExit {
+    /// Control left the block normally; carry on from the cpu's pc.
+    Continue,
+    /// The instruction at the cpu's pc has to go through the interpreter.
+    Interpret,
+}
+
+pub struct BlockEngine {
+    blocks: HashMap<usize,Block>,
+    is_code: Vec<bool>,
+}
+
+impl BlockEngine {
+    pub fn new() -> BlockEngine {
+        BlockEngine { blocks: HashMap::new(), is_code: vec![false; MAX_MEM_ADDR as usize + 1] }
+    }
+
+    /// Throws away every compiled block.
+    pub fn flush(&mut self) {
+        self.blocks.clear();
+        for c in self.is_code.iter_mut() { *c = false; }
+    }
+
+    /// Runs the block at the cpu's pc, compiling it first if needed.
+    /// Returns how many instructions were executed and why it stopped.
+    pub fn run_block(&mut self, cpu: &mut Cpu, bus: &mut Bus, console: &mut dyn Console) -> (u64, BlockExit) {
+        let start = cpu.pc();
+        if !self.blocks.contains_key(&start) {
+            let block = compile(start, bus);
+            if block.ops.is_empty() { return (0, BlockExit::Interpret); }
+            for &(addr, next, _, _) in block.ops.iter() {
+                for a in addr..next {
+                    if let Some(c) = self.is_code.get_mut(a) { *c = true; }
+                }
+            }
+            self.blocks.insert(start, block);
+        }
+
+        let mut executed = 0;
+        let mut modified = false;
+        {
+            let block = &self.blocks[&start];
+            for &(addr, next, instruction, ref op) in block.ops.iter() {
+                cpu.set_pc(next);
+                let flow = op(cpu, bus, console);
+                if let Flow::Fallback = flow {} else { cpu.record_instruction(instruction); }
+                match flow {
+                    Flow::Next => {},
+                    Flow::Jump(target) => { cpu.set_pc(target); return (executed + 1, BlockExit::Continue); },
+                    Flow::Halt => {
+                        cpu.set_pc(addr);
+                        cpu.set_state(CpuState::Halted);
+                        return (executed + 1, BlockExit::Continue);
+                    },
+                    Flow::Wrote(target) => {
+                        if self.is_code.get(target).cloned().unwrap_or(false) {
+                            executed += 1;
+                            modified = true;
+                            break;
+                        }
+                    },
+                    Flow::Fallback => { cpu.set_pc(addr); return (executed, BlockExit::Interpret); },
+                }
+                executed += 1;
+            }
+        }
+
+        // self-modifying code: the rest of this block, and possibly others,
+        // may be stale. Start again from scratch.
+        if modified { self.flush(); }
+
+        // a block cut short by `in`, a fault or its length limit carries on
+        // in the interpreter, which will also compile a new block after it
+        (executed, if modified { BlockExit::Continue } else { BlockExit::Interpret })
+    }
+}
+
+fn compile(start: usize, bus: &Bus) -> Block {
+    let mut ops = Vec::new();
+    let mut pc = start;
+
+    while ops.len() < MAX_BLOCK_LEN && pc <= MAX_MEM_ADDR as usize {
+        let opcode = match bus.read_word(pc).ok().map(Opcode::decode) {
+            Some(Ok(opcode)) => opcode,
+            _ => break,
+        };
+        let mut args = [Operand::Imm(0); 3];
+        let mut words = [0; 3];
+        let mut valid = true;
+        for i in 0..opcode.argc() {
+            let word = bus.read_word(pc + 1 + i).ok();
+            words[i] = word.unwrap_or(0);
+            match word.map(Addr::map) {
+                Some(Ok(Addr::Register(r)))  => args[i] = Operand::Reg(r),
+                Some(Ok(Addr::Immediate(v))) => args[i] = Operand::Imm(v),
+                _ => valid = false,
+            }
+        }
+        if !valid { break; }
+
+        let next = pc + 1 + opcode.argc();
+        let op = match compile_op(opcode, args) {
+            Some(op) => op,
+            None => break,
+        };
+        ops.push((pc, next, (opcode, words[0], words[1], words[2]), op));
+
+        match opcode {
+            Opcode::Jmp | Opcode::Jt | Opcode::Jf | Opcode::Call | Opcode::Ret | Opcode::Halt => break,
+            _ => pc = next,
+        }
+    }
+
+    Block { ops: ops }
+}
+
+fn dest(a: Operand) -> Option<usize> {
+    match a {
+        Operand::Reg(r) => Some(r),
+        Operand::Imm(_) => None,
+    }
+}
+
+fn jump_to(target: WORD) -> Flow {
+    if target > MAX_MEM_ADDR { Flow::Fallback } else { Flow::Jump(target as usize) }
+}
+
+/// Builds the closure for one instruction, or returns None if it is one the
+/// interpreter has to run.
+fn compile_op(opcode: Opcode, args: [Operand; 3]) -> Option<Op> {
+    let [a, b, c] = args;
+    let op: Op = match opcode {
+        Opcode::Halt => Box::new(|_, _, _| Flow::Halt),
+        Opcode::Set => {
+            let r = dest(a)?;
+            Box::new(move |cpu, _, _| { let v = b.get(cpu); cpu.register_put(r, v); Flow::Next })
+        },
+        Opcode::Push => Box::new(move |cpu, bus, _| { bus.push_word(a.get(cpu)); Flow::Next }),
+        Opcode::Pop => {
+            let r = dest(a)?;
+            Box::new(move |cpu, bus, _| match bus.pop_word() {
+                Ok(v) => { cpu.register_put(r, v); Flow::Next },
+                Err(_) => Flow::Fallback,
+            })
+        },
+        Opcode::Eq => {
+            let r = dest(a)?;
+            Box::new(move |cpu, _, _| { let v = if b.get(cpu) == c.get(cpu) { 1 } else { 0 }; cpu.register_put(r, v); Flow::Next })
+        },
+        Opcode::Gt => {
+            let r = dest(a)?;
+            Box::new(move |cpu, _, _| { let v = if b.get(cpu) > c.get(cpu) { 1 } else { 0 }; cpu.register_put(r, v); Flow::Next })
+        },
+        Opcode::Jmp => Box::new(move |cpu, _, _| jump_to(a.get(cpu))),
+        Opcode::Jt => Box::new(move |cpu, _, _| if a.get(cpu) != 0 { jump_to(b.get(cpu)) } else { Flow::Next }),
+        Opcode::Jf => Box::new(move |cpu, _, _| if a.get(cpu) == 0 { jump_to(b.get(cpu)) } else { Flow::Next }),
+        Opcode::Add => {
+            let r = dest(a)?;
+            Box::new(move |cpu, _, _| { let v = ((b.get(cpu) as u32 + c.get(cpu) as u32) % MODULO as u32) as WORD; cpu.register_put(r, v); Flow::Next })
+        },
+        Opcode::Mult => {
+            let r = dest(a)?;
+            Box::new(move |cpu, _, _| {
+                let v = ((b.get(cpu) as u32 * c.get(cpu) as u32) % MODULO as u32) as WORD;
+                cpu.register_put(r, v);
+                Flow::Next
+            })
+        },
+        Opcode::Mod => {
+            let r = dest(a)?;
+            Box::new(move |cpu, _, _| {
+                let divisor = c.get(cpu);
+                if divisor == 0 { return Flow::Fallback; }
+                let v = b.get(cpu) % divisor;
+                cpu.register_put(r, v);
+                Flow::Next
+            })
+        },
+        Opcode::And => {
+            let r = dest(a)?;
+            Box::new(move |cpu, _, _| { let v = b.get(cpu).bitand(c.get(cpu)); cpu.register_put(r, v); Flow::Next })
+        },
+        Opcode::Or => {
+            let r = dest(a)?;
+            Box::new(move |cpu, _, _| { let v = b.get(cpu).bitor(c.get(cpu)); cpu.register_put(r, v); Flow::Next })
+        },
+        Opcode::Not => {
+            let r = dest(a)?;
+            Box::new(move |cpu, _, _| { let v = (!b.get(cpu)).bitand(0x7FFF); cpu.register_put(r, v); Flow::Next })
+        },
+        Opcode::Rmem => {
+            let r = dest(a)?;
+            Box::new(move |cpu, bus, _| match bus.read_word(b.get(cpu) as usize) {
+                Ok(v) => { cpu.register_put(r, v); Flow::Next },
+                Err(_) => Flow::Fallback,
+            })
+        },
+        Opcode::Wmem => Box::new(move |cpu, bus, _| {
+            let addr = a.get(cpu) as usize;
+            match bus.write_word(addr, b.get(cpu)) {
+                Ok(_) => Flow::Wrote(addr),
+                Err(_) => Flow::Fallback,
+            }
+        }),
+        Opcode::Call => Box::new(move |cpu, bus, _| {
+            let target = a.get(cpu);
+            if target > MAX_MEM_ADDR { return Flow::Fallback; }
+            let frame = Frame { caller: cpu.pc() - 2, callee: target as usize, depth: bus.stack_depth() };
+            bus.push_word(cpu.pc() as WORD);
+            cpu.call_stack_mut().push(frame);
+            Flow::Jump(target as usize)
+        }),
+        Opcode::Ret => Box::new(|cpu, bus, _| {
+            let depth = bus.stack_depth();
+            match bus.pop_word() {
+                Ok(v) => {
+                    let pc = cpu.pc() - 1;
+                    cpu.call_stack_mut().ret(pc, v, depth);
+                    Flow::Jump(v as usize)
+                },
+                Err(_) => Flow::Fallback,
+            }
+        }),
+        Opcode::Out => Box::new(move |cpu, _, console| match console.write_char(a.get(cpu)) {
+            Ok(_) => Flow::Next,
+            Err(_) => Flow::Fallback,
+        }),
+        Opcode::In => return None,
+        Opcode::Noop => Box::new(|_, _, _| Flow::Next),
+    };
+    Some(op)
+}
\ No newline at end of file
@@ -1,25 +1,41 @@
+use super::cpu::Instruction;
+use super::decode_cache::DecodeCache;
 use super::memory::Memory;
 use super::stack::Stack;
+use super::error::Fault;
 use super::WORD;
 
 pub struct Bus {
     memory: Memory,
     stack: Stack,
+    decoded: DecodeCache,
 }
 
 impl Bus {
     pub fn new() -> Bus {
-        Bus { memory: Memory::new(), stack: Stack::new() }
+        Bus { memory: Memory::new(), stack: Stack::new(), decoded: DecodeCache::new() }
     }
 
-    //pub fn memory(&mut self) -> &Memory { &self.memory }
-    //pub fn stack(&mut self) -> &Stack { &self.stack }
+    pub fn from_parts(memory: Memory, stack: Stack) -> Bus {
+        Bus { memory: memory, stack: stack, decoded: DecodeCache::new() }
+    }
+
+    pub fn memory(&self) -> &Memory { &self.memory }
+    pub fn stack(&self) -> &Stack { &self.stack }
+
+    pub fn read_word(&self, addr: usize) -> Result<WORD,Fault> { self.memory.read(addr).ok_or(Fault::InvalidAddress(addr)) }
+    pub fn write_word(&mut self, addr: usize, value: WORD) -> Result<(),Fault> {
+        self.decoded.invalidate(addr);
+        self.memory.write(addr, value).ok_or(Fault::InvalidAddress(addr))
+    }
+
+    pub fn cached_instruction(&self, addr: usize) -> Option<Instruction> { self.decoded.get(addr) }
+    pub fn cache_instruction(&mut self, addr: usize, instruction: Instruction) { self.decoded.insert(addr, instruction); }
 
-    pub fn read_word(&self, addr: usize) -> WORD { self.memory.read(addr) }
-    pub fn write_word(&mut self, addr: usize, value: WORD) { self.memory.write(addr, value); }
+    pub fn stack_depth(&self) -> usize { self.stack.len() }
 
     pub fn push_word(&mut self, value: WORD) { self.stack.push(value); }
-    pub fn pop_word(&mut self) -> Result<WORD,String> { self.stack.pop().ok_or(format!("Attempted to pop from empty stack")) }
+    pub fn pop_word(&mut self) -> Result<WORD,Fault> { self.stack.pop().ok_or(Fault::StackUnderflow) }
 
     // pub fn reset(&mut self) {
     //     self.memory = Memory::new();
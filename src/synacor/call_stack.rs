@@ -0,0 +1,83 @@
+use super::WORD;
+
+/// One active `call`, tracked alongside the data stack it shares with
+/// `push`/`pop`.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Frame {
+    pub caller: usize,
+    pub callee: usize,
+    /// Data stack depth before the return address was pushed.
+    pub depth: usize,
+}
+
+impl Frame {
+    pub fn return_address(&self) -> WORD { (self.caller + 2) as WORD }
+}
+
+/// A `ret` that did not line up with the innermost frame.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct CallMismatch {
+    pub pc: usize,
+    /// Where the `ret` actually went.
+    pub target: WORD,
+    /// Where the innermost frame expected to return to, if there was one.
+    pub expected: Option<WORD>,
+    /// Data stack depth before the `ret`.
+    pub depth: usize,
+}
+
+const MAX_MISMATCHES: usize = 16;
+
+#[derive(Debug)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    mismatches: Vec<CallMismatch>,
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack { frames: Vec::new(), mismatches: Vec::new() }
+    }
+
+    /// Outermost frame first.
+    pub fn frames(&self) -> &[Frame] { &self.frames }
+
+    /// The most recent mismatched returns, oldest first.
+    pub fn mismatches(&self) -> &[CallMismatch] { &self.mismatches }
+
+    pub fn push(&mut self, frame: Frame) { self.frames.push(frame); }
+    pub fn pop(&mut self) -> Option<Frame> { self.frames.pop() }
+
+    /// Records a `ret` at `pc` that popped `target` off a stack that was
+    /// `depth` deep. Returns the frames it closed, innermost first.
+    pub fn ret(&mut self, pc: usize, target: WORD, depth: usize) -> Vec<Frame> {
+        // a frame whose return address sat in the slot this `ret` pops, or
+        // above it, is over whether or not the `ret` goes where it expected
+        let mut closed = Vec::new();
+        while self.frames.last().map_or(false, |f| f.depth + 1 >= depth) {
+            closed.extend(self.frames.pop());
+        }
+
+        match closed.iter().find(|f| f.depth + 1 == depth).map(|f| f.return_address()) {
+            Some(addr) => if addr != target { self.mismatch(pc, target, Some(addr), depth); },
+            None => match self.frames.last().map(|f| f.return_address()) {
+                Some(addr) if addr == target => {
+                    // right address, but something was left on the stack
+                    // in between
+                    self.mismatch(pc, target, Some(addr), depth);
+                    closed.extend(self.frames.pop());
+                },
+                Some(addr) => self.mismatch(pc, target, Some(addr), depth),
+                // nothing to compare against, as after restoring a snapshot,
+                // which doesn't keep the call stack
+                None => {},
+            },
+        }
+        closed
+    }
+
+    fn mismatch(&mut self, pc: usize, target: WORD, expected: Option<WORD>, depth: usize) {
+        if self.mismatches.len() == MAX_MISMATCHES { self.mismatches.remove(0); }
+        self.mismatches.push(CallMismatch { pc: pc, target: target, expected: expected, depth: depth });
+    }
+}
\ No newline at end of file
@@ -0,0 +1,225 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self,BufReader,Bytes};
+use std::io::prelude::*;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,Ordering};
+use super::WORD;
+
+/// The VM's terminal: `in` reads from it, `out` writes to it.
+pub trait Console {
+    /// Returns `Ok(None)` when there is no input to give right now.
+    fn read_char(&mut self) -> Result<Option<WORD>,String>;
+    fn write_char(&mut self, c: WORD) -> Result<(),String>;
+    fn flush(&mut self) -> Result<(),String> { Ok(()) }
+    /// Whether the input has ended, so there will never be any more.
+    fn at_end(&self) -> bool { false }
+}
+
+/// Reads from stdin and writes to stdout.
+pub struct StdConsole {
+    ended: bool,
+}
+
+impl StdConsole {
+    pub fn new() -> StdConsole { StdConsole { ended: false } }
+}
+
+impl Console for StdConsole {
+    fn read_char(&mut self) -> Result<Option<WORD>,String> {
+        loop {
+            match io::stdin().bytes().next() {
+                Some(Ok(0x0D)) => continue, // skip CHR(13) on windows
+                Some(Ok(b))    => return Ok(Some(b as WORD)),
+                Some(Err(e))   => return Err(format!("Error reading from keyboard: {}", e)),
+                None           => { self.ended = true; return Ok(None); },
+            }
+        }
+    }
+
+    fn at_end(&self) -> bool { self.ended }
+
+    fn write_char(&mut self, c: WORD) -> Result<(),String> {
+        print!("{}", c as u8 as char);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(),String> {
+        io::stdout().flush().map_err(|e| format!("Error flushing stdout: {}", e))
+    }
+}
+
+/// Lines typed into the program that were meant for the host, oldest
+/// first. Clones share the same queue.
+#[derive(Clone)]
+pub struct MetaCommands(Rc<RefCell<VecDeque<String>>>);
+
+impl MetaCommands {
+    pub fn new() -> MetaCommands { MetaCommands(Rc::new(RefCell::new(VecDeque::new()))) }
+    pub fn push(&self, command: String) { self.0.borrow_mut().push_back(command); }
+    pub fn take(&self) -> Option<String> { self.0.borrow_mut().pop_front() }
+}
+
+/// Wraps another console so the host can stop the VM at an `in`. Input is
+/// read a line at a time. The escape key, if any, is swallowed and sets the
+/// interrupt flag; so does a line starting with the meta-command prefix,
+/// which is queued for the host instead of reaching the program. A line
+/// read while the flag is set (Ctrl-C while blocked reading) is held back
+/// until the program resumes, so the VM stops with `in` still to run.
+pub struct EscapeConsole {
+    inner: Box<dyn Console>,
+    key: Option<WORD>,
+    interrupt: Arc<AtomicBool>,
+    pending: VecDeque<WORD>,
+    meta: Option<(String, MetaCommands)>,
+}
+
+impl EscapeConsole {
+    pub fn new(inner: Box<dyn Console>, key: Option<WORD>, interrupt: Arc<AtomicBool>) -> EscapeConsole {
+        EscapeConsole { inner: inner, key: key, interrupt: interrupt, pending: VecDeque::new(), meta: None }
+    }
+
+    /// Starts picking out lines that begin with `prefix`. They are queued,
+    /// without the prefix, on the returned handle.
+    pub fn intercept(&mut self, prefix: &str) -> MetaCommands {
+        let commands = MetaCommands::new();
+        self.meta = Some((prefix.to_string(), commands.clone()));
+        commands
+    }
+}
+
+impl Console for EscapeConsole {
+    fn read_char(&mut self) -> Result<Option<WORD>,String> {
+        if self.pending.is_empty() {
+            while self.pending.back() != Some(&(b'\n' as WORD)) {
+                match self.inner.read_char()? {
+                    Some(c) => self.pending.push_back(c),
+                    None => break,
+                }
+            }
+            if let Some((ref prefix, ref commands)) = self.meta {
+                let line = self.pending.iter().map(|&c| c as u8 as char).collect::<String>();
+                if line.starts_with(&prefix[..]) {
+                    commands.push(line[prefix.len()..].trim().to_string());
+                    self.pending.clear();
+                    self.interrupt.store(true, Ordering::SeqCst);
+                    return Ok(None);
+                }
+            }
+        }
+
+        if self.interrupt.load(Ordering::SeqCst) { return Ok(None); }
+        match self.pending.pop_front() {
+            Some(c) if Some(c) == self.key => {
+                self.interrupt.store(true, Ordering::SeqCst);
+                Ok(None)
+            },
+            c => Ok(c),
+        }
+    }
+
+    fn write_char(&mut self, c: WORD) -> Result<(),String> { self.inner.write_char(c) }
+
+    fn flush(&mut self) -> Result<(),String> { self.inner.flush() }
+
+    fn at_end(&self) -> bool { self.pending.is_empty() && self.inner.at_end() }
+}
+
+struct Buffers {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+/// In-memory console. Clones share the same buffers, so a caller can keep
+/// a handle to feed input and collect output after handing one to the VM.
+#[derive(Clone)]
+pub struct BufferConsole(Rc<RefCell<Buffers>>);
+
+impl BufferConsole {
+    pub fn new() -> BufferConsole {
+        BufferConsole(Rc::new(RefCell::new(Buffers { input: VecDeque::new(), output: Vec::new() })))
+    }
+
+    pub fn push_input(&self, input: &str) {
+        self.0.borrow_mut().input.extend(input.bytes());
+    }
+
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow().output).into_owned()
+    }
+
+    pub fn take_output(&self) -> String {
+        let bytes = ::std::mem::replace(&mut self.0.borrow_mut().output, Vec::new());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Console for BufferConsole {
+    fn read_char(&mut self) -> Result<Option<WORD>,String> {
+        Ok(self.0.borrow_mut().input.pop_front().map(|b| b as WORD))
+    }
+
+    fn write_char(&mut self, c: WORD) -> Result<(),String> {
+        self.0.borrow_mut().output.push(c as u8);
+        Ok(())
+    }
+}
+
+/// Reads input from one file and appends output to another. Either side
+/// may be omitted; with no input file there is never any input, writing
+/// with no output file discards the character.
+pub struct FileConsole {
+    input: Option<Bytes<BufReader<File>>>,
+    output: Option<File>,
+    ended: bool, // the input file has been read to the end
+}
+
+impl FileConsole {
+    pub fn open<P: AsRef<Path>>(input: Option<P>, output: Option<P>) -> io::Result<FileConsole> {
+        let input = match input {
+            Some(p) => Some(BufReader::new(File::open(p)?).bytes()),
+            None => None,
+        };
+        let output = match output {
+            Some(p) => Some(File::create(p)?),
+            None => None,
+        };
+        Ok(FileConsole { input: input, output: output, ended: false })
+    }
+}
+
+impl Console for FileConsole {
+    fn read_char(&mut self) -> Result<Option<WORD>,String> {
+        let input = match self.input {
+            Some(ref mut input) => input,
+            None => return Ok(None),
+        };
+        loop {
+            match input.next() {
+                Some(Ok(0x0D)) => continue,
+                Some(Ok(b)) => return Ok(Some(b as WORD)),
+                Some(Err(e)) => return Err(format!("Error reading input file: {}", e)),
+                None => { self.ended = true; return Ok(None); },
+            }
+        }
+    }
+
+    fn write_char(&mut self, c: WORD) -> Result<(),String> {
+        match self.output {
+            Some(ref mut f) => f.write_all(&[c as u8]).map_err(|e| format!("Error writing output file: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(),String> {
+        match self.output {
+            Some(ref mut f) => f.flush().map_err(|e| format!("Error flushing output file: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    fn at_end(&self) -> bool { self.ended }
+}
\ No newline at end of file
@@ -1,16 +1,22 @@
 use std::collections::VecDeque;
+use std::io::{self,Write};
 use super::addr::Addr;
 use super::bus::Bus;
+use super::call_stack::{CallStack,Frame};
+use super::console::Console;
+use super::effect::{Effect,Read};
+use super::error::{Fault,VmError};
 use super::{OpRes,WORD};
 use super::opcode::Opcode;
+use super::undo::UndoRecord;
 
 pub const MAX_MEM_ADDR:  WORD   = 0x7FFF;
 pub const MODULO:        WORD   = 0x8000;
 pub const NUM_REGISTERS: usize = 8;
 
-type Instruction = (Opcode,WORD,WORD,WORD);
+pub type Instruction = (Opcode,WORD,WORD,WORD);
 
-#[derive(Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,PartialEq)]
 pub enum CpuState {
     NotStarted,
     Running,
@@ -18,12 +24,29 @@ pub enum CpuState {
     Error
 }
 
+/// How to treat things the arch-spec leaves undefined.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Strictness {
+    /// Trap with an error: values above 32767 reaching an operation (through
+    /// a register loaded by `rmem`), `mod` by zero, and `out` of non-ASCII.
+    Strict,
+    /// Carry on: arithmetic is done modulo 32768 whatever the inputs, `mod`
+    /// by zero gives back the dividend, and memory is the full 64K words,
+    /// so `rmem`/`wmem` may use addresses above 32767.
+    Lenient,
+}
+
 #[derive(Debug)]
 pub struct Cpu {
     state: CpuState,
     registers: [WORD; NUM_REGISTERS], // arch has 8 16-bit registers
     pc: usize, // instruction pointer
     prev_instructions: VecDeque<Instruction>,
+    effects: Vec<Effect>, // changes made by the instruction currently executing
+    reads: Vec<Read>, // values read by the instruction currently executing
+    record_reads: bool,
+    calls: CallStack, // shadow of the call/ret pairs on the data stack
+    strictness: Strictness,
 }
 
 impl Cpu {
@@ -33,6 +56,11 @@ impl Cpu {
             registers: [0; NUM_REGISTERS],
             pc: 0,
             prev_instructions: VecDeque::new(),
+            effects: Vec::new(),
+            reads: Vec::new(),
+            record_reads: false,
+            calls: CallStack::new(),
+            strictness: Strictness::Lenient,
         }
     }
 
@@ -43,94 +71,162 @@ impl Cpu {
     //     self.sp = 0;
     // }
 
+    pub fn from_parts(state: CpuState, registers: [WORD; NUM_REGISTERS], pc: usize) -> Cpu {
+        Cpu {
+            state: state,
+            registers: registers,
+            pc: pc,
+            prev_instructions: VecDeque::new(),
+            effects: Vec::new(),
+            reads: Vec::new(),
+            record_reads: false,
+            calls: CallStack::new(),
+            strictness: Strictness::Lenient,
+        }
+    }
+
+    pub fn registers(&self) -> &[WORD; NUM_REGISTERS] { &self.registers }
     pub fn register_get(&self, reg: usize) -> WORD { self.registers[reg] }
     pub fn register_put(&mut self, reg: usize, value: WORD) { self.registers[reg] = value; }
 
-    pub fn start(&mut self) {
+    pub fn start(&mut self) -> Result<(),VmError> {
         if self.state != CpuState::NotStarted {
-            panic!("Attempted to start CPU from invalid state: {:?}", self.state);
+            return Err(VmError::InvalidState { state: self.state });
         }
         self.state = CpuState::Running;
+        Ok(())
     }
 
-    pub fn step(&mut self, bus: &mut Bus, debug_mode: bool) {
+    pub fn step(&mut self, bus: &mut Bus, console: &mut dyn Console) -> Result<(),VmError> {
         // ensure we're running, first.
         if self.state != CpuState::Running {
-            panic!("Attempted to execute instruction while in invalid state: {:?}", self.state);
+            return Err(VmError::InvalidState { state: self.state });
         }
 
         // fetch
-        if self.pc > 0x7FFF { panic!("PC beyond memory addressing range: {:#04X}", self.pc); }
+        if self.pc > MAX_MEM_ADDR as usize {
+            self.state = CpuState::Error;
+            return Err(VmError::PcOutOfRange { pc: self.pc });
+        }
+
+        let pc = self.pc;
+        self.effects.clear();
+        self.reads.clear();
+        let instruction = match bus.cached_instruction(pc) {
+            Some(instruction) => {
+                self.pc += pc_advance(instruction.0);
+                instruction
+            },
+            None => match self.fetch(pc, bus).and_then(|code| self.decode(code, bus)) {
+                Ok(instruction) => {
+                    bus.cache_instruction(pc, instruction);
+                    instruction
+                },
+                Err(e) => {
+                    self.state = CpuState::Error;
+                    return Err(e);
+                }
+            },
+        };
 
-        let instruction_code = self.fetch(self.pc, bus);
-        let instruction = self.decode(instruction_code, bus);
+        self.record_instruction(instruction);
 
-        if debug_mode { println!("@{:#04X}: {:?}", self.pc, instruction); }
-        
+        self.execute(pc, instruction, bus, console)
+    }
+
+    /// Reverses an instruction recorded in the undo log.
+    pub fn revert(&mut self, bus: &mut Bus, record: &UndoRecord) {
+        for effect in record.effects.iter().rev() {
+            match *effect {
+                Effect::RegisterWrite { reg, old, .. } => self.registers[reg] = old,
+                Effect::MemoryWrite { addr, old, .. }  => { let _ = bus.write_word(addr, old); },
+                Effect::StackPush(_)                   => { let _ = bus.pop_word(); },
+                Effect::StackPop(v)                    => bus.push_word(v),
+                Effect::FramePush(_)                   => { self.calls.pop(); },
+                Effect::FramePop(f)                    => self.calls.push(f),
+            }
+        }
+        self.pc = record.pc;
+        self.state = record.state;
+        self.effects.clear();
+    }
+
+    pub fn strictness(&self) -> Strictness { self.strictness }
+    pub fn set_strictness(&mut self, strictness: Strictness) { self.strictness = strictness; }
+
+    pub fn call_stack(&self) -> &CallStack { &self.calls }
+    pub fn call_stack_mut(&mut self) -> &mut CallStack { &mut self.calls }
+
+    /// The most recently decoded instruction.
+    pub fn last_instruction(&self) -> Option<Instruction> { self.prev_instructions.front().cloned() }
+
+    /// Writes out the cpu state, call backtrace and last 5 instructions,
+    /// for reporting a fault.
+    pub fn dump(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "CPU State:")?;
+        writeln!(out, "{:?}", self)?;
+        writeln!(out, "Backtrace:")?;
+        for (n, frame) in self.calls.frames().iter().rev().enumerate() {
+            writeln!(out, "  #{} in {:#06X} called from {:#06X} (stack depth {})", n, frame.callee, frame.caller, frame.depth)?;
+        }
+        for m in self.calls.mismatches() {
+            writeln!(out, "  mismatched ret @{:#06X} to {:#06X}, expected {:?} (stack depth {})", m.pc, m.target, m.expected, m.depth)?;
+        }
+        writeln!(out, "Last 5 instructions:")?;
+        for instr in self.prev_instructions.iter().rev() {
+            writeln!(out, "  {:?}", instr)?;
+        }
+        Ok(())
+    }
+
+    /// Adds an instruction to the last-5 history, for instructions executed
+    /// outside `step`.
+    pub fn record_instruction(&mut self, instruction: Instruction) {
         self.prev_instructions.push_front(instruction);
         if self.prev_instructions.len() > 5 { self.prev_instructions.pop_back(); }
-
-        self.execute(instruction, bus);
     }
 
+    /// Changes made by the most recently executed instruction.
+    pub fn effects(&self) -> &[Effect] { &self.effects }
+
+    /// Collect the register and memory reads each instruction makes. Off by
+    /// default, as nearly every instruction reads something.
+    pub fn set_record_reads(&mut self, record: bool) { self.record_reads = record; }
+    pub fn reads(&self) -> &[Read] { &self.reads }
+
     pub fn is_running(&self) -> bool {
         self.state == CpuState::Running
     }
 
     pub fn state(&self) -> &CpuState { &self.state }
 
-    #[cfg(test)]
     pub fn pc(&self) -> usize { self.pc }
+    pub fn set_pc(&mut self, pc: usize) { self.pc = pc; }
+    pub fn set_state(&mut self, state: CpuState) { self.state = state; }
 
     /* PRIVATE */
 
-    fn fetch(&self, addr: usize, bus: &Bus) -> WORD { bus.read_word(addr) }
+    fn fetch(&self, addr: usize, bus: &Bus) -> Result<WORD,VmError> {
+        bus.read_word(addr).map_err(|f| f.at(addr, vec![]))
+    }
 
-    fn decode(&mut self, instruction_code: WORD, bus: &Bus) -> Instruction {
+    fn decode(&mut self, instruction_code: WORD, bus: &Bus) -> Result<Instruction,VmError> {
         let pc = self.pc.clone();
-        let rw = |off| bus.read_word(pc + off);
-
-        let instruction = match instruction_code {
-             0 => (Opcode::Halt, 0,     0,     0),
-             1 => (Opcode::Set,  rw(1), rw(2), 0),
-             2 => (Opcode::Push, rw(1), 0,     0),
-             3 => (Opcode::Pop,  rw(1), 0,     0),
-             4 => (Opcode::Eq,   rw(1), rw(2), rw(3)),
-             5 => (Opcode::Gt,   rw(1), rw(2), rw(3)),
-             6 => (Opcode::Jmp,  rw(1), 0,     0),
-             7 => (Opcode::Jt,   rw(1), rw(2), 0),
-             8 => (Opcode::Jf,   rw(1), rw(2), 0),
-             9 => (Opcode::Add,  rw(1), rw(2), rw(3)),
-            10 => (Opcode::Mult, rw(1), rw(2), rw(3)),
-            11 => (Opcode::Mod,  rw(1), rw(2), rw(3)),
-            12 => (Opcode::And,  rw(1), rw(2), rw(3)),
-            13 => (Opcode::Or,   rw(1), rw(2), rw(3)),
-            14 => (Opcode::Not,  rw(1), rw(2), 0),
-            15 => (Opcode::Rmem, rw(1), rw(2), 0),
-            16 => (Opcode::Wmem, rw(1), rw(2), 0),
-            17 => (Opcode::Call, rw(1), 0,     0),
-            18 => (Opcode::Ret,  0,     0,     0),
-            19 => (Opcode::Out,  rw(1), 0,     0),
-            20 => (Opcode::In,   rw(1), 0,     0),
-            21 => (Opcode::Noop, 0,     0,     0),
-            _  => panic!("Unknown instruction code: {}", instruction_code),
-        };
+        // operands past the end of memory read as 0, and are caught by
+        // the operation itself if it actually uses them
+        let rw = |off| bus.read_word(pc + off).unwrap_or(0);
 
-        let pc_advance = match instruction_code {
-            0                        => 0, // don't advance IP on HLT
-            18 | 21                  => 1, // no arguments
-            2 | 3 | 6 | 17 | 19 | 20 => 2, // one argument
-            1 | 7 | 8 | 14 | 15 | 16 => 3, // two arguments
-            _                        => 4, // three arguments
-        };
+        let opcode = Opcode::decode(instruction_code).map_err(|f| f.at(pc, vec![instruction_code]))?;
+        let arg = |n| if n <= opcode.argc() { rw(n) } else { 0 };
+        let instruction = (opcode, arg(1), arg(2), arg(3));
 
-        self.pc += pc_advance;
+        self.pc += pc_advance(instruction.0);
 
-        instruction
+        Ok(instruction)
     }
 
-    fn execute(&mut self, instruction: Instruction, bus: &mut Bus) {
-        let res: OpRes<String> = match instruction {
+    fn execute(&mut self, pc: usize, instruction: Instruction, bus: &mut Bus, console: &mut dyn Console) -> Result<(),VmError> {
+        let res: OpRes<Fault> = match instruction {
             /*  0 */ (Opcode::Halt, _, _, _) => op::halt(self,bus),
             /*  1 */ (Opcode::Set,  a, b, _) => op::set(self,bus,a,b),
             /*  2 */ (Opcode::Push, a, _, _) => op::push(self,bus,a),
@@ -150,124 +246,168 @@ impl Cpu {
             /* 16 */ (Opcode::Wmem, a, b, _) => op::wmem(self,bus,a,b),
             /* 17 */ (Opcode::Call, a, _, _) => op::call(self,bus,a),
             /* 18 */ (Opcode::Ret,  _, _, _) => op::ret(self,bus),
-            /* 19 */ (Opcode::Out,  a, _, _) => op::outc(self,a),
-            /* 20 */ (Opcode::In,   a, _, _) => op::inc(self,a),
+            /* 19 */ (Opcode::Out,  a, _, _) => op::outc(self,console,a),
+            /* 20 */ (Opcode::In,   a, _, _) => op::inc(self,console,a),
             /* 21 */ (Opcode::Noop, _, _, _) => op::noop(),
         };
 
         if res.is_failure() {
-            self.state = CpuState::Error;
-            println!("ERROR");
-            println!("Instruction failed: {}", res.unwrap_failure());
-            println!("CPU State:");
-            println!("{:?}", self);
-            println!("Last 5 instructions:");
-            loop {
-                let instr = self.prev_instructions.pop_back();
-                if instr.is_none() { break; }
-                let instr = instr.unwrap();
-                println!("  {:?}", instr);
+            let fault = res.unwrap_failure();
+            match fault {
+                Fault::Console(_) | Fault::InputUnavailable | Fault::EndOfInput => {
+                    // not a machine fault: rewind so the instruction can be
+                    // retried once the console has something to give us
+                    self.pc = pc;
+                    return Err(fault.at(pc, raw_words(instruction)));
+                },
+                _ => {},
             }
+
+            self.state = CpuState::Error;
+            return Err(fault.at(pc, raw_words(instruction)));
         }
+        Ok(())
     }
 }
 
+fn pc_advance(opcode: Opcode) -> usize {
+    match opcode {
+        Opcode::Halt => 0, // don't advance IP on HLT
+        op           => 1 + op.argc(),
+    }
+}
+
+pub fn raw_words(instruction: Instruction) -> Vec<WORD> {
+    let (opcode, a, b, c) = instruction;
+    let mut words = vec![opcode.into(), a, b, c];
+    words.truncate(1 + opcode.argc());
+    words
+}
+
 mod op {
-    use std;
-    use std::io::Read;
     use std::ops::{BitAnd,BitOr};
     use super::{OpRes,WORD};
     use super::OpRes::{Success,Failure};
 
-    use super::{Addr,Bus,Cpu,CpuState,MAX_MEM_ADDR,MODULO};
+    use super::{Addr,Bus,Console,Cpu,CpuState,Effect,Fault,Frame,Read,Strictness,MAX_MEM_ADDR,MODULO};
 
-    fn map_val(v: WORD, cpu: &Cpu) -> WORD {
-        match Addr::map(v) {
-            Addr::Register(r) => cpu.register_get(r),
-            Addr::Immediate(i) => i,
+    fn map_val(v: WORD, cpu: &mut Cpu) -> Result<WORD,Fault> {
+        match Addr::map(v)? {
+            Addr::Register(r) => {
+                let value = cpu.register_get(r);
+                if cpu.record_reads { cpu.reads.push(Read::Register { reg: r, value: value }); }
+                check_val(cpu, value)
+            },
+            Addr::Immediate(i) => Ok(i),
         }
     }
 
-    fn map_reg(v: WORD) -> Result<usize,String> {
-        match Addr::map(v) {
+    // registers can only hold an out-of-range value if rmem put one there
+    fn check_val(cpu: &Cpu, v: WORD) -> Result<WORD,Fault> {
+        if cpu.strictness == Strictness::Strict && v > MAX_MEM_ADDR {
+            Err(Fault::InvalidValue(v))
+        } else {
+            Ok(v)
+        }
+    }
+
+    fn map_reg(v: WORD) -> Result<usize,Fault> {
+        match Addr::map(v)? {
             Addr::Register(r) => Ok(r),
-            Addr::Immediate(i) => Err(format!("Invalid register: {}", i)),
+            Addr::Immediate(i) => Err(Fault::InvalidRegister(i)),
         }
     }
 
-    fn read_char() -> Result<WORD, String> {
-        loop {
-            let input = std::io::stdin()
-            .bytes()
-            .next()
-            .and_then(|r| r.ok())
-            .map(|b| b as WORD);
+    // all state changes go through these so they end up in cpu.effects
 
-            if input.is_some() && input.unwrap() == 0x0D { continue; } // skip CHR(13) on windows
+    fn put_reg(cpu: &mut Cpu, reg: usize, value: WORD) {
+        cpu.effects.push(Effect::RegisterWrite { reg: reg, old: cpu.registers[reg], new: value });
+        cpu.registers[reg] = value;
+    }
 
-            return input.ok_or(format!("Error reading from keyboard"))
-        }
+    fn read_mem(cpu: &mut Cpu, bus: &Bus, addr: usize) -> Result<WORD,Fault> {
+        let value = bus.read_word(addr)?;
+        if cpu.record_reads { cpu.reads.push(Read::Memory { addr: addr, value: value }); }
+        Ok(value)
+    }
+
+    fn write_mem(cpu: &mut Cpu, bus: &mut Bus, addr: usize, value: WORD) -> Result<(),Fault> {
+        let old = bus.read_word(addr)?;
+        bus.write_word(addr, value)?;
+        cpu.effects.push(Effect::MemoryWrite { addr: addr, old: old, new: value });
+        Ok(())
+    }
+
+    fn push_word(cpu: &mut Cpu, bus: &mut Bus, value: WORD) {
+        bus.push_word(value);
+        cpu.effects.push(Effect::StackPush(value));
+    }
+
+    fn pop_word(cpu: &mut Cpu, bus: &mut Bus) -> Result<WORD,Fault> {
+        let value = bus.pop_word()?;
+        cpu.effects.push(Effect::StackPop(value));
+        Ok(value)
     }
 
     /* CPU OPS HERE */
 
-    pub fn halt(cpu: &mut Cpu, _bus: &Bus) -> OpRes<String> {
+    pub fn halt(cpu: &mut Cpu, _bus: &Bus) -> OpRes<Fault> {
         cpu.state = CpuState::Halted;
         Success
     }
 
-    pub fn set(cpu: &mut Cpu, _bus: &Bus, a: WORD, b: WORD) -> OpRes<String> {
+    pub fn set(cpu: &mut Cpu, _bus: &Bus, a: WORD, b: WORD) -> OpRes<Fault> {
         let reg = map_reg(a)?;
-        let data = map_val(b,cpu);
-        cpu.register_put(reg, data);
+        let data = map_val(b,cpu)?;
+        put_reg(cpu, reg, data);
         Success
     }
 
-    pub fn push(cpu: &mut Cpu, bus: &mut Bus, a: WORD) -> OpRes<String> {
-        let _a = map_val(a,cpu);
-        bus.push_word(_a);
+    pub fn push(cpu: &mut Cpu, bus: &mut Bus, a: WORD) -> OpRes<Fault> {
+        let _a = map_val(a,cpu)?;
+        push_word(cpu, bus, _a);
         Success
     }
 
-    pub fn pop(cpu: &mut Cpu, bus: &mut Bus, a: WORD) -> OpRes<String> {
+    pub fn pop(cpu: &mut Cpu, bus: &mut Bus, a: WORD) -> OpRes<Fault> {
         let reg = map_reg(a)?;
-        let data = bus.pop_word()?;
-        cpu.register_put(reg, data);
+        let data = pop_word(cpu, bus)?;
+        put_reg(cpu, reg, data);
         Success
     }
 
-    pub fn eq(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<String> {
+    pub fn eq(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<Fault> {
         let reg = map_reg(a)?;
-        let _b = map_val(b,cpu);
-        let _c = map_val(c,cpu);
-        cpu.register_put(reg, if _b == _c { 1 } else { 0 });
+        let _b = map_val(b,cpu)?;
+        let _c = map_val(c,cpu)?;
+        put_reg(cpu, reg, if _b == _c { 1 } else { 0 });
         Success
     }
 
-    pub fn gt(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<String> {
+    pub fn gt(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<Fault> {
         let reg = map_reg(a)?;
-        let _b = map_val(b,cpu);
-        let _c = map_val(c,cpu);
-        cpu.register_put(reg, if _b > _c { 1 } else { 0 });
+        let _b = map_val(b,cpu)?;
+        let _c = map_val(c,cpu)?;
+        put_reg(cpu, reg, if _b > _c { 1 } else { 0 });
         Success
     }
 
-    pub fn jmp(cpu: &mut Cpu, a: WORD) -> OpRes<String> {
-        let _a = map_val(a,cpu);
+    pub fn jmp(cpu: &mut Cpu, a: WORD) -> OpRes<Fault> {
+        let _a = map_val(a,cpu)?;
         if _a > MAX_MEM_ADDR {
-            Failure(format!("Jump target invalid: {:#04X}", _a))
+            Failure(Fault::InvalidAddress(_a as usize))
         } else {
             cpu.pc = _a as usize;
             Success
         }
     }
 
-    pub fn jt(cpu: &mut Cpu, a: WORD, b: WORD) -> OpRes<String> {
-        let _a = map_val(a,cpu);
-        let _b = map_val(b,cpu);
+    pub fn jt(cpu: &mut Cpu, a: WORD, b: WORD) -> OpRes<Fault> {
+        let _a = map_val(a,cpu)?;
+        let _b = map_val(b,cpu)?;
         if _a != 0 {
             if _b > MAX_MEM_ADDR {
-                return Failure(format!("Jump target invalid: {:#04X}", _b))
+                return Failure(Fault::InvalidAddress(_b as usize))
             } else {
                 cpu.pc = _b as usize;
             }
@@ -275,12 +415,12 @@ mod op {
         Success
     }
 
-    pub fn jf(cpu: &mut Cpu, a: WORD, b: WORD) -> OpRes<String> {
-        let _a = map_val(a,cpu);
-        let _b = map_val(b,cpu);
+    pub fn jf(cpu: &mut Cpu, a: WORD, b: WORD) -> OpRes<Fault> {
+        let _a = map_val(a,cpu)?;
+        let _b = map_val(b,cpu)?;
         if _a == 0 {
             if _b > MAX_MEM_ADDR {
-                return Failure(format!("Jump target invalid: {:#04X}", _b))
+                return Failure(Fault::InvalidAddress(_b as usize))
             } else {
                 cpu.pc = _b as usize;
             }
@@ -288,96 +428,119 @@ mod op {
         Success
     }
 
-    pub fn add(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<String> {
+    pub fn add(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<Fault> {
         let reg = map_reg(a)?;
-        let _b = map_val(b,cpu);
-        let _c = map_val(c,cpu);
-        cpu.register_put(reg, (_b + _c) % MODULO);
+        let _b = map_val(b,cpu)?;
+        let _c = map_val(c,cpu)?;
+        put_reg(cpu, reg, ((_b as u32 + _c as u32) % MODULO as u32) as WORD);
         Success
     }
 
-    pub fn mult(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<String> {
+    pub fn mult(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<Fault> {
         let reg = map_reg(a)?;
-        let _b = map_val(b,cpu);
-        let _c = map_val(c,cpu);
-        cpu.register_put(reg, ((_b as u32 * _c as u32) % MODULO as u32) as WORD);
+        let _b = map_val(b,cpu)?;
+        let _c = map_val(c,cpu)?;
+        put_reg(cpu, reg, ((_b as u32 * _c as u32) % MODULO as u32) as WORD);
         Success
     }
 
-    pub fn rmdr(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<String> {
+    pub fn rmdr(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<Fault> {
         let reg = map_reg(a)?;
-        let _b = map_val(b,cpu);
-        let _c = map_val(c,cpu);
-        cpu.register_put(reg, _b % _c);
+        let _b = map_val(b,cpu)?;
+        let _c = map_val(c,cpu)?;
+        let result = match _c {
+            0 if cpu.strictness == Strictness::Strict => return Failure(Fault::DivideByZero),
+            0 => _b,
+            _ => _b % _c,
+        };
+        put_reg(cpu, reg, result);
         Success
     }
 
-    pub fn and(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<String> {
+    pub fn and(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<Fault> {
         let reg = map_reg(a)?;
-        let _b = map_val(b,cpu);
-        let _c = map_val(c,cpu);
-        cpu.register_put(reg, _b.bitand(_c));
+        let _b = map_val(b,cpu)?;
+        let _c = map_val(c,cpu)?;
+        put_reg(cpu, reg, _b.bitand(_c));
         Success
     }
 
-    pub fn or(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<String> {
+    pub fn or(cpu: &mut Cpu, a: WORD, b: WORD, c: WORD) -> OpRes<Fault> {
         let reg = map_reg(a)?;
-        let _b = map_val(b,cpu);
-        let _c = map_val(c,cpu);
-        cpu.register_put(reg, _b.bitor(_c));
+        let _b = map_val(b,cpu)?;
+        let _c = map_val(c,cpu)?;
+        put_reg(cpu, reg, _b.bitor(_c));
         Success
     }
 
-    pub fn not(cpu: &mut Cpu, a: WORD, b: WORD) -> OpRes<String> {
+    pub fn not(cpu: &mut Cpu, a: WORD, b: WORD) -> OpRes<Fault> {
         let reg = map_reg(a)?;
-        let _b = map_val(b,cpu);
-        cpu.register_put(reg, (!_b).bitand(0x7FFF));
+        let _b = map_val(b,cpu)?;
+        put_reg(cpu, reg, (!_b).bitand(0x7FFF));
         Success
     }
 
-    pub fn rmem(cpu: &mut Cpu, bus: &Bus, a: WORD, b: WORD) -> OpRes<String> {
+    pub fn rmem(cpu: &mut Cpu, bus: &Bus, a: WORD, b: WORD) -> OpRes<Fault> {
         let reg = map_reg(a)?;
-        let _b = map_val(b,cpu);
-        cpu.register_put(reg, bus.read_word(_b as usize));
+        let _b = map_val(b,cpu)?;
+        let data = read_mem(cpu, bus, _b as usize)?;
+        let data = check_val(cpu, data)?;
+        put_reg(cpu, reg, data);
         Success
     }
 
-    pub fn wmem(cpu: &mut Cpu, bus: &mut Bus, a: WORD, b: WORD) -> OpRes<String> {
-        let _a = map_val(a,cpu);
-        let _b = map_val(b,cpu);
-        bus.write_word(_a as usize, _b);
+    pub fn wmem(cpu: &mut Cpu, bus: &mut Bus, a: WORD, b: WORD) -> OpRes<Fault> {
+        let _a = map_val(a,cpu)?;
+        let _b = map_val(b,cpu)?;
+        write_mem(cpu, bus, _a as usize, _b)?;
         Success
     }
 
-    pub fn call(cpu: &mut Cpu, bus: &mut Bus, a: WORD) -> OpRes<String> {
-        let _a = map_val(a,cpu);
+    pub fn call(cpu: &mut Cpu, bus: &mut Bus, a: WORD) -> OpRes<Fault> {
+        let _a = map_val(a,cpu)?;
         if _a > MAX_MEM_ADDR {
-            Failure(format!("Called invalid memory address: {:#04X}", _a))
+            Failure(Fault::InvalidAddress(_a as usize))
         } else {
-            bus.push_word(cpu.pc as WORD);
+            let ret_addr = cpu.pc as WORD;
+            let frame = Frame { caller: cpu.pc - 2, callee: _a as usize, depth: bus.stack_depth() };
+            push_word(cpu, bus, ret_addr);
+            cpu.calls.push(frame);
+            cpu.effects.push(Effect::FramePush(frame));
             cpu.pc = _a as usize;
             Success
         }
     }
 
-    pub fn ret(cpu: &mut Cpu, bus: &mut Bus) -> OpRes<String> {
-        let data = bus.pop_word()?;
+    pub fn ret(cpu: &mut Cpu, bus: &mut Bus) -> OpRes<Fault> {
+        let depth = bus.stack_depth();
+        // the spec makes a `ret` with nothing to return to a halt, not a fault
+        if depth == 0 {
+            cpu.pc -= 1;
+            cpu.state = CpuState::Halted;
+            return Success;
+        }
+        let data = pop_word(cpu, bus)?;
+        for frame in cpu.calls.ret(cpu.pc - 1, data, depth) {
+            cpu.effects.push(Effect::FramePop(frame));
+        }
         cpu.pc = data as usize;
         Success
     }
 
-    pub fn outc(cpu: &mut Cpu, a: WORD) -> OpRes<String> {
-        let _a = map_val(a,cpu);
-        print!("{}", _a as u8 as char);
+    pub fn outc(cpu: &mut Cpu, console: &mut dyn Console, a: WORD) -> OpRes<Fault> {
+        let _a = map_val(a,cpu)?;
+        if cpu.strictness == Strictness::Strict && _a > 0x7F { return Failure(Fault::InvalidValue(_a)); }
+        console.write_char(_a)?;
         Success
     }
 
-    pub fn inc(cpu: &mut Cpu, a: WORD) -> OpRes<String> {
+    pub fn inc(cpu: &mut Cpu, console: &mut dyn Console, a: WORD) -> OpRes<Fault> {
         let reg = map_reg(a)?;
-        let c = read_char()?;
-        cpu.register_put(reg, c);
+        console.flush()?;
+        let c = console.read_char()?.ok_or_else(|| if console.at_end() { Fault::EndOfInput } else { Fault::InputUnavailable })?;
+        put_reg(cpu, reg, c);
         Success
     }
 
-    pub fn noop() -> OpRes<String> { Success }
+    pub fn noop() -> OpRes<Fault> { Success }
 }
\ No newline at end of file
@@ -0,0 +1,34 @@
+use super::cpu::{Instruction,MAX_MEM_ADDR};
+
+/// Decoded instructions keyed by the address they start at. A write to
+/// memory drops every entry whose words it could have touched.
+pub struct DecodeCache {
+    entries: Vec<Option<Instruction>>,
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        DecodeCache { entries: vec![None; MAX_MEM_ADDR as usize + 1] }
+    }
+
+    pub fn get(&self, addr: usize) -> Option<Instruction> {
+        self.entries.get(addr).and_then(|e| *e)
+    }
+
+    pub fn insert(&mut self, addr: usize, instruction: Instruction) {
+        if let Some(entry) = self.entries.get_mut(addr) { *entry = Some(instruction); }
+    }
+
+    /// Instructions are at most 4 words long, so a write to `addr` can only
+    /// affect ones starting at `addr - 3 ..= addr`.
+    pub fn invalidate(&mut self, addr: usize) {
+        let start = if addr >= 3 { addr - 3 } else { 0 };
+        for a in start..(addr + 1) {
+            if let Some(entry) = self.entries.get_mut(a) { *entry = None; }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for entry in self.entries.iter_mut() { *entry = None; }
+    }
+}
\ No newline at end of file
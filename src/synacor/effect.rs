@@ -0,0 +1,22 @@
+use super::WORD;
+use super::call_stack::Frame;
+
+/// A single change an instruction made to machine state, with enough
+/// information to reverse it.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Effect {
+    RegisterWrite { reg: usize, old: WORD, new: WORD },
+    MemoryWrite { addr: usize, old: WORD, new: WORD },
+    StackPush(WORD),
+    StackPop(WORD),
+    FramePush(Frame),
+    FramePop(Frame),
+}
+
+/// A value an instruction read from a register or memory. Only collected
+/// while something is watching for reads; see `Cpu::set_record_reads`.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Read {
+    Register { reg: usize, value: WORD },
+    Memory { addr: usize, value: WORD },
+}
\ No newline at end of file
@@ -0,0 +1,124 @@
+use std::fmt;
+use super::WORD;
+use super::cpu::CpuState;
+
+/// Something that went wrong inside a single operation. The CPU attaches
+/// the pc and raw instruction words to turn it into a `VmError`.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Fault {
+    InvalidOpcode(WORD),
+    InvalidOperand(WORD),
+    InvalidRegister(WORD),
+    InvalidValue(WORD),
+    InvalidAddress(usize),
+    StackUnderflow,
+    DivideByZero,
+    Console(String),
+    InputUnavailable,
+    EndOfInput,
+}
+
+impl From<String> for Fault {
+    fn from(s: String) -> Self { Fault::Console(s) }
+}
+
+impl Fault {
+    pub fn at(self, pc: usize, raw: Vec<WORD>) -> VmError {
+        match self {
+            Fault::InvalidOpcode(_)   => VmError::IllegalOpcode { pc: pc, raw: raw },
+            Fault::InvalidOperand(v)  => VmError::InvalidOperand { pc: pc, raw: raw, value: v },
+            Fault::InvalidRegister(v) => VmError::InvalidRegister { pc: pc, raw: raw, value: v },
+            Fault::InvalidValue(v)    => VmError::InvalidValue { pc: pc, raw: raw, value: v },
+            Fault::InvalidAddress(a)  => VmError::InvalidAddress { pc: pc, raw: raw, address: a },
+            Fault::StackUnderflow     => VmError::StackUnderflow { pc: pc, raw: raw },
+            Fault::DivideByZero       => VmError::DivideByZero { pc: pc, raw: raw },
+            Fault::Console(m)         => VmError::Console { pc: pc, raw: raw, message: m },
+            Fault::InputUnavailable   => VmError::InputUnavailable { pc: pc, raw: raw },
+            Fault::EndOfInput         => VmError::EndOfInput { pc: pc, raw: raw },
+        }
+    }
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum VmError {
+    /// An operand word in the invalid range 0x8008..=0xFFFF.
+    InvalidOperand { pc: usize, raw: Vec<WORD>, value: WORD },
+    /// A literal where the instruction needs a register to write to.
+    InvalidRegister { pc: usize, raw: Vec<WORD>, value: WORD },
+    /// A value above 32767 where the spec only allows 0..32767 (strict only).
+    InvalidValue { pc: usize, raw: Vec<WORD>, value: WORD },
+    /// A jump, call or memory access outside of addressable memory.
+    InvalidAddress { pc: usize, raw: Vec<WORD>, address: usize },
+    IllegalOpcode { pc: usize, raw: Vec<WORD> },
+    PcOutOfRange { pc: usize },
+    StackUnderflow { pc: usize, raw: Vec<WORD> },
+    DivideByZero { pc: usize, raw: Vec<WORD> },
+    Console { pc: usize, raw: Vec<WORD>, message: String },
+    /// `in` found nothing to read. The CPU is left ready to retry it.
+    InputUnavailable { pc: usize, raw: Vec<WORD> },
+    /// `in` found the console's input has ended. The CPU is left ready to
+    /// retry it, as for `InputUnavailable`.
+    EndOfInput { pc: usize, raw: Vec<WORD> },
+    InvalidState { state: CpuState },
+    ProgramTooLarge { size: usize },
+    Trace { message: String },
+}
+
+impl VmError {
+    pub fn pc(&self) -> Option<usize> {
+        match *self {
+            VmError::InvalidOperand { pc, .. }  |
+            VmError::InvalidRegister { pc, .. } |
+            VmError::InvalidValue { pc, .. }    |
+            VmError::InvalidAddress { pc, .. }  |
+            VmError::IllegalOpcode { pc, .. }   |
+            VmError::PcOutOfRange { pc }        |
+            VmError::StackUnderflow { pc, .. }  |
+            VmError::DivideByZero { pc, .. }    |
+            VmError::Console { pc, .. }         |
+            VmError::InputUnavailable { pc, .. } |
+            VmError::EndOfInput { pc, .. }      => Some(pc),
+            VmError::InvalidState { .. }        |
+            VmError::ProgramTooLarge { .. }     |
+            VmError::Trace { .. }               => None,
+        }
+    }
+
+    pub fn raw(&self) -> &[WORD] {
+        match *self {
+            VmError::InvalidOperand { ref raw, .. }  |
+            VmError::InvalidRegister { ref raw, .. } |
+            VmError::InvalidValue { ref raw, .. }    |
+            VmError::InvalidAddress { ref raw, .. }  |
+            VmError::IllegalOpcode { ref raw, .. }   |
+            VmError::StackUnderflow { ref raw, .. }  |
+            VmError::DivideByZero { ref raw, .. }    |
+            VmError::Console { ref raw, .. }         |
+            VmError::InputUnavailable { ref raw, .. } |
+            VmError::EndOfInput { ref raw, .. }      => &raw[..],
+            _                                        => &[],
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VmError::InvalidOperand { value, .. }     => write!(f, "Invalid operand: {:#06X}", value)?,
+            VmError::InvalidRegister { value, .. }    => write!(f, "Invalid register: {}", value)?,
+            VmError::InvalidValue { value, .. }       => write!(f, "Value out of range: {:#06X}", value)?,
+            VmError::InvalidAddress { address, .. }   => write!(f, "Address out of range: {:#06X}", address)?,
+            VmError::IllegalOpcode { ref raw, .. }    => write!(f, "Unknown instruction code: {}", raw[0])?,
+            VmError::PcOutOfRange { pc }              => return write!(f, "PC beyond memory addressing range: {:#06X}", pc),
+            VmError::StackUnderflow { .. }            => write!(f, "Attempted to pop from empty stack")?,
+            VmError::DivideByZero { .. }              => write!(f, "Division by zero")?,
+            VmError::Console { ref message, .. }      => write!(f, "{}", message)?,
+            VmError::InputUnavailable { .. }          => write!(f, "Waiting for input")?,
+            VmError::EndOfInput { .. }                => write!(f, "End of input")?,
+            VmError::InvalidState { ref state }       => return write!(f, "CPU in invalid state: {:?}", state),
+            VmError::ProgramTooLarge { size }         => return write!(f, "Program of {} words does not fit in memory", size),
+            VmError::Trace { ref message }            => return write!(f, "Unable to write trace: {}", message),
+        }
+        write!(f, " @{:#06X} {:?}", self.pc().unwrap_or(0), self.raw())
+    }
+}
\ No newline at end of file
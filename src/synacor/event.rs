@@ -0,0 +1,43 @@
+use super::cpu::Instruction;
+use super::effect::{Effect,Read};
+use super::error::VmError;
+
+/// Something a bounded run can stop on.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// The instruction at `pc` is about to be fetched.
+    Fetch { pc: usize },
+    /// The instruction at `pc` has just executed. `reads` is empty unless
+    /// the VM was asked to record them.
+    Executed { pc: usize, instruction: Instruction, effects: &'a [Effect], reads: &'a [Read] },
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum StopReason {
+    BudgetExhausted,
+    BreakpointHit,
+    WaitingForInput,
+    /// The VM's interrupt flag was set.
+    Interrupted,
+    Halted,
+    Error(VmError),
+}
+
+/// Decides when `Vm::run_until` stops. An address stops before the
+/// instruction there is executed; a closure sees every event.
+pub trait StopCondition {
+    fn should_stop(&mut self, event: &Event) -> bool;
+}
+
+impl StopCondition for usize {
+    fn should_stop(&mut self, event: &Event) -> bool {
+        match *event {
+            Event::Fetch { pc } => pc == *self,
+            _ => false,
+        }
+    }
+}
+
+impl<F: FnMut(&Event) -> bool> StopCondition for F {
+    fn should_stop(&mut self, event: &Event) -> bool { self(event) }
+}
\ No newline at end of file
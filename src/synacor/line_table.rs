@@ -0,0 +1,132 @@
+//! Line tables, written by the assembler beside the binary: the source line
+//! every instruction and data declaration came from.
+//!
+//! ```text
+//! file hello.asm
+//! 0x0000 2 1
+//! 0x0002 4 2
+//! ```
+//!
+//! Each entry is an address, how many words start there, and a line. A
+//! `file` line names the source of the entries after it; a relative name is
+//! taken relative to the line table's own directory when it is loaded.
+
+use std::fs::File;
+use std::io::{self,BufWriter};
+use std::io::prelude::*;
+use std::path::Path;
+use super::parse_number;
+
+/// Where the words at `address..address + size` came from.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Entry {
+    pub address: usize,
+    pub size: usize,
+    pub file: usize,
+    pub line: usize,
+}
+
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct LineTable {
+    files: Vec<String>,
+    entries: Vec<Entry>, // sorted by address
+}
+
+impl LineTable {
+    pub fn new() -> LineTable { LineTable::default() }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+    pub fn files(&self) -> &[String] { &self.files }
+
+    /// Records that the `size` words at `address` came from `line` of `file`.
+    pub fn add(&mut self, address: usize, size: usize, file: &str, line: usize) {
+        let file = match self.files.iter().position(|f| f == file) {
+            Some(i) => i,
+            None => { self.files.push(file.to_string()); self.files.len() - 1 },
+        };
+        let i = match self.entries.binary_search_by_key(&address, |e| e.address) { Ok(i) | Err(i) => i };
+        self.entries.insert(i, Entry { address: address, size: size, file: file, line: line });
+    }
+
+    /// The entry covering `address`.
+    pub fn entry(&self, address: usize) -> Option<Entry> {
+        let i = match self.entries.binary_search_by_key(&address, |e| e.address) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let entry = self.entries[i];
+        if address < entry.address + entry.size.max(1) { Some(entry) } else { None }
+    }
+
+    /// The file and line `address` came from.
+    pub fn location(&self, address: usize) -> Option<(&str, usize)> {
+        self.entry(address).map(|e| (&self.files[e.file][..], e.line))
+    }
+
+    /// Whether the code for a line starts at `address`.
+    pub fn starts_line(&self, address: usize) -> bool {
+        self.entry(address).map_or(false, |e| e.address == address)
+    }
+
+    /// The first address of `line` in `file`, or of the next line that has
+    /// any code, along with that line. Files match on their last path
+    /// components, so `hello.asm` finds `src/hello.asm` and the other way
+    /// round.
+    pub fn address_of(&self, file: &str, line: usize) -> Option<(usize, usize)> {
+        let index = self.files.iter().position(|f| Path::new(f).ends_with(file) || Path::new(file).ends_with(f))?;
+        self.entries.iter()
+            .filter(|e| e.file == index && e.line >= line)
+            .min_by_key(|e| (e.line, e.address))
+            .map(|e| (e.address, e.line))
+    }
+
+    pub fn parse(text: &str) -> Result<LineTable, String> {
+        let mut table = LineTable::new();
+        let mut file = None;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() { continue; }
+            if line.starts_with("file ") {
+                file = Some(line[5..].trim().to_string());
+                continue;
+            }
+            let fields = line.split_whitespace().map(parse_number).collect::<Vec<_>>();
+            match (&file, &fields[..]) {
+                (&Some(_), &[_, _, Some(0)]) => return Err(format!("line {}: source lines are numbered from 1", n + 1)),
+                (&Some(ref f), &[Some(address), Some(size), Some(l)]) => table.add(address, size, f, l),
+                (&None, _) => return Err(format!("line {}: entry before any 'file' line", n + 1)),
+                _ => return Err(format!("line {}: expected 'ADDRESS SIZE LINE', found '{}'", n + 1, line)),
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<LineTable> {
+        let mut text = String::new();
+        File::open(path.as_ref())?.read_to_string(&mut text)?;
+        let mut table = LineTable::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(dir) = path.as_ref().parent() { table.resolve_files(dir); }
+        Ok(table)
+    }
+
+    /// Turns file names relative to `dir` into paths.
+    pub fn resolve_files(&mut self, dir: &Path) {
+        for file in self.files.iter_mut() {
+            *file = dir.join(&file[..]).to_string_lossy().into_owned();
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let mut file = None;
+        for e in self.entries.iter() {
+            if file != Some(e.file) {
+                writeln!(out, "file {}", self.files[e.file])?;
+                file = Some(e.file);
+            }
+            writeln!(out, "{:#06X} {} {}", e.address, e.size, e.line)?;
+        }
+        out.flush()
+    }
+}
\ No newline at end of file
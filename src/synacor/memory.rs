@@ -7,6 +7,16 @@ impl Memory {
         Memory([0; MEMORY_SIZE])
     }
 
-    pub fn read(&self, address: usize) -> WORD { self.0[address] }
-    pub fn write(&mut self, address: usize, value: WORD) { self.0[address] = value; }
+    pub fn from_words(words: Vec<WORD>) -> Memory {
+        let mut memory = Memory::new();
+        for (addr, word) in words.into_iter().take(MEMORY_SIZE).enumerate() {
+            memory.0[addr] = word;
+        }
+        memory
+    }
+
+    pub fn words(&self) -> &[WORD] { &self.0 }
+
+    pub fn read(&self, address: usize) -> Option<WORD> { self.0.get(address).cloned() }
+    pub fn write(&mut self, address: usize, value: WORD) -> Option<()> { self.0.get_mut(address).map(|w| *w = value) }
 }
\ No newline at end of file
@@ -1,14 +1,44 @@
+pub mod block_engine;
 pub mod bus;
+pub mod call_stack;
+pub mod console;
 pub mod cpu;
+pub mod decode_cache;
+pub mod effect;
+pub mod error;
+pub mod event;
+pub mod line_table;
 pub mod memory;
 pub mod opcode;
+pub mod provenance;
+pub mod snapshot;
 pub mod stack;
+pub mod symbols;
+pub mod trace;
+pub mod undo;
 pub mod vm;
 
 mod addr;
 mod op_res;
 use self::op_res::OpRes;
 
-pub use self::vm::Vm;
+pub use self::console::{BufferConsole,Console,EscapeConsole,FileConsole,MetaCommands,StdConsole};
+pub use self::error::VmError;
+pub use self::event::{Event,StopCondition,StopReason};
+pub use self::line_table::LineTable;
+pub use self::snapshot::Snapshot;
+pub use self::symbols::Symbols;
+pub use self::trace::Tracer;
+pub use self::cpu::Strictness;
+pub use self::vm::{Engine,Vm};
 
-pub type WORD = u16;
\ No newline at end of file
+pub type WORD = u16;
+
+/// Parses a decimal or 0x-prefixed hexadecimal number.
+pub fn parse_number(s: &str) -> Option<usize> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        usize::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse::<usize>().ok()
+    }
+}
\ No newline at end of file
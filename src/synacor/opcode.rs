@@ -1,4 +1,5 @@
 use super::WORD;
+use super::error::Fault;
 
 #[derive(Clone,Copy,Debug,PartialEq)]
 pub enum Opcode {
@@ -37,6 +38,33 @@ impl Opcode {
         }
     }
 
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::Halt => "halt",
+            Opcode::Set  => "set",
+            Opcode::Push => "push",
+            Opcode::Pop  => "pop",
+            Opcode::Eq   => "eq",
+            Opcode::Gt   => "gt",
+            Opcode::Jmp  => "jmp",
+            Opcode::Jt   => "jt",
+            Opcode::Jf   => "jf",
+            Opcode::Add  => "add",
+            Opcode::Mult => "mult",
+            Opcode::Mod  => "mod",
+            Opcode::And  => "and",
+            Opcode::Or   => "or",
+            Opcode::Not  => "not",
+            Opcode::Rmem => "rmem",
+            Opcode::Wmem => "wmem",
+            Opcode::Call => "call",
+            Opcode::Ret  => "ret",
+            Opcode::Out  => "out",
+            Opcode::In   => "in",
+            Opcode::Noop => "noop",
+        }
+    }
+
     pub fn try_from<'s>(s: &'s str) -> Option<Self> {
         match s {
             "halt" | "hlt" => Some(Opcode::Halt),
@@ -66,9 +94,10 @@ impl Opcode {
     }
 }
 
-impl From<WORD> for Opcode {
-    fn from(w: WORD) -> Self {
-        match w {
+impl Opcode {
+    /// The opcode with instruction code `w`, or `Fault::InvalidOpcode`.
+    pub fn decode(w: WORD) -> Result<Self, Fault> {
+        Ok(match w {
              0 => Opcode::Halt,
              1 => Opcode::Set,
              2 => Opcode::Push,
@@ -91,8 +120,8 @@ impl From<WORD> for Opcode {
             19 => Opcode::Out,
             20 => Opcode::In,
             21 => Opcode::Noop,
-            _ => panic!("Invalid opcode: {}", w),
-        }
+            _ => return Err(Fault::InvalidOpcode(w)),
+        })
     }
 }
 
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use super::WORD;
+use super::cpu::NUM_REGISTERS;
+use super::effect::Effect;
+use super::memory::MEMORY_SIZE;
+
+/// One write to a register or memory word.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Write {
+    /// The instruction that wrote, or None for a change made from outside
+    /// the program, such as by a debugger.
+    pub pc: Option<usize>,
+    /// Instructions executed before the write.
+    pub count: u64,
+    pub old: WORD,
+    pub new: WORD,
+}
+
+/// Who last wrote every register and memory word, plus every write to the
+/// memory ranges asked for with `keep_history`.
+pub struct Provenance {
+    registers: [Option<Write>; NUM_REGISTERS],
+    memory: Vec<Option<Write>>,
+    ranges: Vec<(usize, usize)>, // inclusive
+    history: HashMap<usize, Vec<Write>>,
+}
+
+impl Provenance {
+    pub fn new() -> Provenance {
+        Provenance {
+            registers: [None; NUM_REGISTERS],
+            memory: vec![None; MEMORY_SIZE],
+            ranges: Vec::new(),
+            history: HashMap::new(),
+        }
+    }
+
+    pub fn register(&self, reg: usize) -> Option<Write> { self.registers.get(reg).cloned().unwrap_or(None) }
+    pub fn memory(&self, addr: usize) -> Option<Write> { self.memory.get(addr).cloned().unwrap_or(None) }
+
+    /// Every write to `addr` since its range was added, oldest first.
+    pub fn history(&self, addr: usize) -> &[Write] {
+        self.history.get(&addr).map_or(&[], |h| &h[..])
+    }
+
+    pub fn ranges(&self) -> &[(usize, usize)] { &self.ranges }
+
+    /// Starts keeping every write to `start..=end`, not just the last.
+    pub fn keep_history(&mut self, start: usize, end: usize) {
+        self.ranges.push((start, end));
+    }
+
+    fn keeps_history(&self, addr: usize) -> bool {
+        self.ranges.iter().any(|&(start, end)| addr >= start && addr <= end)
+    }
+
+    /// Records the writes among `effects`, made by the instruction at `pc`.
+    pub fn record(&mut self, pc: Option<usize>, count: u64, effects: &[Effect]) {
+        for effect in effects.iter() {
+            match *effect {
+                Effect::RegisterWrite { reg, old, new } =>
+                    self.registers[reg] = Some(Write { pc: pc, count: count, old: old, new: new }),
+                Effect::MemoryWrite { addr, old, new } => {
+                    let write = Write { pc: pc, count: count, old: old, new: new };
+                    self.memory[addr] = Some(write);
+                    if self.keeps_history(addr) { self.history.entry(addr).or_insert_with(Vec::new).push(write); }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Forgets the writes made by instruction `count` and later, once they
+    /// have been undone. Where no history was kept, the write before them
+    /// isn't known either.
+    pub fn forget_from(&mut self, count: u64) {
+        for reg in self.registers.iter_mut() {
+            if reg.map_or(false, |w| w.count >= count) { *reg = None; }
+        }
+        for (addr, slot) in self.memory.iter_mut().enumerate() {
+            if slot.map_or(false, |w| w.count >= count) {
+                *slot = match self.history.get_mut(&addr) {
+                    Some(history) => {
+                        history.retain(|w| w.count < count);
+                        history.last().cloned()
+                    },
+                    None => None,
+                };
+            }
+        }
+    }
+}
\ No newline at end of file
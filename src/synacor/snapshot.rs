@@ -0,0 +1,114 @@
+//! On-disk machine snapshots.
+//!
+//! All values are little-endian, matching the program binary format:
+//!
+//! ```text
+//! magic     8 bytes   "SYNSNAP\0"
+//! version   u16
+//! state     u16       0 = NotStarted, 1 = Running, 2 = Halted, 3 = Error
+//! pc        u16
+//! registers 8 x u16
+//! memory    65536 x u16
+//! stack_len u32
+//! stack     stack_len x u16, bottom first
+//! ```
+
+use std::fs::File;
+use std::io::{self,BufReader,BufWriter};
+use std::io::prelude::*;
+use std::path::Path;
+use super::WORD;
+use super::cpu::{CpuState,NUM_REGISTERS};
+use super::memory::MEMORY_SIZE;
+
+const MAGIC: &'static [u8; 8] = b"SYNSNAP\0";
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Snapshot {
+    pub state: CpuState,
+    pub pc: usize,
+    pub registers: [WORD; NUM_REGISTERS],
+    pub memory: Vec<WORD>,
+    pub stack: Vec<WORD>,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_word<W: Write>(w: &mut W, value: WORD) -> io::Result<()> {
+    w.write_all(&[(value & 0xFF) as u8, (value >> 8) as u8])
+}
+
+fn read_word<R: Read>(r: &mut R) -> io::Result<WORD> {
+    let mut buf = [0u8; 2];
+    r.read_exact(&mut buf)?;
+    Ok(((buf[1] as WORD) << 8) + buf[0] as WORD)
+}
+
+impl Snapshot {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_to(&mut w)?;
+        w.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Snapshot> {
+        Snapshot::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        write_word(w, SNAPSHOT_VERSION)?;
+        write_word(w, match self.state {
+            CpuState::NotStarted => 0,
+            CpuState::Running    => 1,
+            CpuState::Halted     => 2,
+            CpuState::Error      => 3,
+        })?;
+        write_word(w, self.pc as WORD)?;
+        for r in self.registers.iter() { write_word(w, *r)?; }
+        for m in self.memory.iter() { write_word(w, *m)?; }
+        let len = self.stack.len() as u32;
+        w.write_all(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8])?;
+        for s in self.stack.iter() { write_word(w, *s)?; }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Snapshot> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC { return Err(invalid(format!("Not a snapshot file"))); }
+
+        let version = read_word(r)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid(format!("Unsupported snapshot version {} (expected {})", version, SNAPSHOT_VERSION)));
+        }
+
+        let state = match read_word(r)? {
+            0 => CpuState::NotStarted,
+            1 => CpuState::Running,
+            2 => CpuState::Halted,
+            3 => CpuState::Error,
+            s => return Err(invalid(format!("Invalid CPU state in snapshot: {}", s))),
+        };
+        let pc = read_word(r)? as usize;
+
+        let mut registers = [0; NUM_REGISTERS];
+        for reg in registers.iter_mut() { *reg = read_word(r)?; }
+
+        let mut memory = Vec::with_capacity(MEMORY_SIZE);
+        for _ in 0..MEMORY_SIZE { memory.push(read_word(r)?); }
+
+        let mut len = [0u8; 4];
+        r.read_exact(&mut len)?;
+        let len = (len[0] as usize) | (len[1] as usize) << 8 | (len[2] as usize) << 16 | (len[3] as usize) << 24;
+        // len comes from the file, so let a truncated stack fail the read
+        // rather than allocating whatever it claims up front
+        let mut stack = Vec::new();
+        for _ in 0..len { stack.push(read_word(r)?); }
+
+        Ok(Snapshot { state: state, pc: pc, registers: registers, memory: memory, stack: stack })
+    }
+}
\ No newline at end of file
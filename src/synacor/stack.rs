@@ -7,6 +7,13 @@ impl Stack {
         Stack(Vec::new())
     }
 
+    pub fn from_words(words: Vec<WORD>) -> Stack {
+        Stack(words)
+    }
+
+    pub fn words(&self) -> &[WORD] { &self.0 }
+    pub fn len(&self) -> usize { self.0.len() }
+
     pub fn push(&mut self, value: WORD) {
         self.0.push(value);
     }
@@ -0,0 +1,110 @@
+//! Symbol files, written by the assembler and disassembler: one label per
+//! line, address first.
+//!
+//! ```text
+//! 0x05B2 print_word
+//! 0x0AAC loc_0AAC
+//! ```
+//!
+//! Blank lines and lines starting with `;` are ignored.
+
+use std::collections::{BTreeMap,HashMap};
+use std::fs::File;
+use std::io::{self,BufWriter};
+use std::io::prelude::*;
+use std::path::Path;
+use super::parse_number;
+
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Symbols {
+    by_addr: BTreeMap<usize, String>,
+    by_name: HashMap<String, usize>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols { Symbols::default() }
+
+    /// Symbols for the labels of an assembled program.
+    pub fn from_labels(labels: &HashMap<String, usize>) -> Symbols {
+        let mut symbols = Symbols::new();
+        for (name, &addr) in labels.iter() { symbols.insert(name, addr); }
+        symbols
+    }
+
+    /// Adds a label, or moves an existing one. Where two labels share an
+    /// address, addresses render with whichever sorts first.
+    pub fn insert(&mut self, name: &str, addr: usize) {
+        if let Some(old) = self.by_name.insert(name.to_string(), addr) {
+            if old != addr && self.by_addr.get(&old).map_or(false, |n| n == name) {
+                self.by_addr.remove(&old);
+                let other = self.by_name.iter().filter(|&(_, &a)| a == old).map(|(n, _)| n).min().cloned();
+                if let Some(other) = other { self.by_addr.insert(old, other); }
+            }
+        }
+        let keep = match self.by_addr.get(&addr) {
+            Some(existing) => existing.as_str() < name,
+            None => false,
+        };
+        if !keep { self.by_addr.insert(addr, name.to_string()); }
+    }
+
+    pub fn is_empty(&self) -> bool { self.by_name.is_empty() }
+    pub fn len(&self) -> usize { self.by_name.len() }
+
+    pub fn address(&self, name: &str) -> Option<usize> { self.by_name.get(name).cloned() }
+
+    /// The label at exactly `addr`.
+    pub fn name(&self, addr: usize) -> Option<&str> { self.by_addr.get(&addr).map(|s| s.as_str()) }
+
+    /// `addr` relative to the closest label at or before it, as `name` or
+    /// `name+3`.
+    pub fn describe(&self, addr: usize) -> Option<String> {
+        self.by_addr.range(..addr + 1).next_back().map(|(&base, name)| match addr - base {
+            0 => name.clone(),
+            off => format!("{}+{}", name, off),
+        })
+    }
+
+    /// Looks up `name` or `name+N`, N in decimal or `0x` hex.
+    pub fn resolve(&self, s: &str) -> Option<usize> {
+        match s.find('+') {
+            Some(i) => Some(self.address(s[..i].trim())? + parse_number(s[i + 1..].trim())?),
+            None => self.address(s.trim()),
+        }
+    }
+
+    /// Every label, in address order.
+    pub fn iter<'a>(&'a self) -> Box<dyn Iterator<Item=(&'a str, usize)> + 'a> {
+        let mut all = self.by_name.iter().map(|(name, &addr)| (name.as_str(), addr)).collect::<Vec<_>>();
+        all.sort_by(|a, b| (a.1, a.0).cmp(&(b.1, b.0)));
+        Box::new(all.into_iter())
+    }
+
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') { continue; }
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match (fields.len(), parse_number(fields[0])) {
+                (2, Some(addr)) => symbols.insert(fields[1], addr),
+                _ => return Err(format!("line {}: expected 'ADDRESS NAME', found '{}'", n + 1, line)),
+            }
+        }
+        Ok(symbols)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Symbols> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Symbols::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        for (name, addr) in self.iter() {
+            writeln!(out, "{:#06X} {}", addr, name)?;
+        }
+        out.flush()
+    }
+}
\ No newline at end of file
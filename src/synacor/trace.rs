@@ -0,0 +1,84 @@
+//! Execution traces, one JSON object per executed instruction:
+//!
+//! ```text
+//! {"n":0,"pc":0,"op":"add","raw":[9,32768,32769,4],"args":[0,0,4],
+//!  "reg_writes":[{"reg":0,"old":0,"new":4}],"mem_writes":[],"push":[],"pop":[]}
+//! ```
+//!
+//! `args` are the operand values after register lookup, taken before the
+//! instruction ran; an operand in the invalid range is `null`. When the VM
+//! has symbols, `"sym":"print_word+3"` follows `pc`.
+
+use std::fs::File;
+use std::io::{self,BufWriter};
+use std::io::prelude::*;
+use std::path::Path;
+use json;
+use super::WORD;
+use super::cpu::{Instruction,NUM_REGISTERS,raw_words};
+use super::effect::Effect;
+use super::opcode::Opcode;
+
+pub struct Tracer {
+    out: Box<dyn Write>,
+    ranges: Vec<(usize,usize)>,
+    opcodes: Vec<Opcode>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>) -> Tracer {
+        Tracer { out: out, ranges: Vec::new(), opcodes: Vec::new() }
+    }
+
+    pub fn to_file<P: AsRef<Path>>(path: P) -> io::Result<Tracer> {
+        Ok(Tracer::new(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    /// Only trace instructions at `start..=end`. May be given more than
+    /// once; with no ranges every address is traced.
+    pub fn add_range(&mut self, start: usize, end: usize) { self.ranges.push((start, end)); }
+
+    /// Only trace these opcodes. May be given more than once; with no
+    /// opcodes every instruction is traced.
+    pub fn add_opcode(&mut self, opcode: Opcode) { self.opcodes.push(opcode); }
+
+    pub fn wants(&self, pc: usize, opcode: Opcode) -> bool {
+        (self.ranges.is_empty() || self.ranges.iter().any(|&(s, e)| pc >= s && pc <= e)) &&
+        (self.opcodes.is_empty() || self.opcodes.contains(&opcode))
+    }
+
+    pub fn record(&mut self, count: u64, pc: usize, symbol: Option<String>, instruction: Instruction, registers: &[WORD; NUM_REGISTERS], effects: &[Effect]) -> io::Result<()> {
+        let raw = raw_words(instruction);
+        let args = raw[1..].iter().map(|&w| match w {
+            w if w < 0x8000 => w.to_string(),
+            w if w < 0x8008 => registers[(w - 0x8000) as usize].to_string(),
+            _               => format!("null"),
+        }).collect::<Vec<_>>();
+
+        let mut reg_writes = Vec::new();
+        let mut mem_writes = Vec::new();
+        let mut pushes = Vec::new();
+        let mut pops = Vec::new();
+        for effect in effects {
+            match *effect {
+                Effect::RegisterWrite { reg, old, new } => reg_writes.push(format!("{{\"reg\":{},\"old\":{},\"new\":{}}}", reg, old, new)),
+                Effect::MemoryWrite { addr, old, new }  => mem_writes.push(format!("{{\"addr\":{},\"old\":{},\"new\":{}}}", addr, old, new)),
+                Effect::StackPush(v)                    => pushes.push(v.to_string()),
+                Effect::StackPop(v)                     => pops.push(v.to_string()),
+                Effect::FramePush(_) | Effect::FramePop(_) => {},
+            }
+        }
+
+        let symbol = symbol.map_or(String::new(), |s| format!(",\"sym\":{}", json::stringify(s)));
+        writeln!(self.out, "{{\"n\":{},\"pc\":{}{},\"op\":\"{}\",\"raw\":[{}],\"args\":[{}],\"reg_writes\":[{}],\"mem_writes\":[{}],\"push\":[{}],\"pop\":[{}]}}",
+            count, pc, symbol, instruction.0.mnemonic(),
+            raw.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(","),
+            args.join(","),
+            reg_writes.join(","),
+            mem_writes.join(","),
+            pushes.join(","),
+            pops.join(","))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> { self.out.flush() }
+}
\ No newline at end of file
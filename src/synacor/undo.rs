@@ -0,0 +1,45 @@
+use std::collections::VecDeque;
+use super::cpu::CpuState;
+use super::effect::Effect;
+
+/// Everything needed to put the machine back the way it was before one
+/// instruction executed.
+#[derive(Clone,Debug)]
+pub struct UndoRecord {
+    pub pc: usize,
+    pub count: u64,
+    pub state: CpuState,
+    pub effects: Vec<Effect>,
+}
+
+/// Bounded history of executed instructions, newest last. Once full, the
+/// oldest record is dropped for every new one.
+pub struct UndoLog {
+    records: VecDeque<UndoRecord>,
+    capacity: usize,
+}
+
+impl UndoLog {
+    pub fn new(capacity: usize) -> UndoLog {
+        UndoLog { records: VecDeque::new(), capacity: capacity }
+    }
+
+    pub fn capacity(&self) -> usize { self.capacity }
+    pub fn len(&self) -> usize { self.records.len() }
+    pub fn is_enabled(&self) -> bool { self.capacity > 0 }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.records.len() > capacity { self.records.pop_front(); }
+    }
+
+    pub fn record(&mut self, record: UndoRecord) {
+        if self.capacity == 0 { return; }
+        if self.records.len() == self.capacity { self.records.pop_front(); }
+        self.records.push_back(record);
+    }
+
+    pub fn pop(&mut self) -> Option<UndoRecord> { self.records.pop_back() }
+
+    pub fn clear(&mut self) { self.records.clear(); }
+}
\ No newline at end of file
@@ -1,39 +1,354 @@
-use super::cpu::Cpu;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,Ordering};
+use super::block_engine::{BlockEngine,BlockExit};
+use super::cpu::{Cpu,CpuState,Strictness};
 use super::bus::Bus;
+use super::call_stack::Frame;
+use super::console::{Console,StdConsole};
+use super::error::VmError;
+use super::effect::Effect;
+use super::event::{Event,StopCondition,StopReason};
+use super::line_table::LineTable;
+use super::memory::Memory;
+use super::provenance::Provenance;
+use super::snapshot::Snapshot;
+use super::stack::Stack;
+use super::symbols::Symbols;
+use super::trace::Tracer;
+use super::undo::{UndoLog,UndoRecord};
 use super::WORD;
 
+/// How `Vm::run` executes instructions when nothing is recording them.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Engine {
+    Interpreter,
+    Blocks,
+}
+
 pub struct Vm {
     cpu: Cpu,
     bus: Bus,
+    console: Box<dyn Console>,
+    engine: Engine,
+    blocks: BlockEngine,
+    undo: UndoLog,
+    tracer: Option<Tracer>,
+    provenance: Option<Provenance>,
+    symbols: Symbols,
+    line_table: LineTable,
+    instruction_count: u64,
+    interrupt: Arc<AtomicBool>,
     debug_mode: bool,
 }
 
 impl Vm {
     pub fn new() -> Vm {
+        Vm::with_console(Box::new(StdConsole::new()))
+    }
+
+    pub fn with_console(console: Box<dyn Console>) -> Vm {
         Vm {
             cpu: Cpu::new(),
             bus: Bus::new(),
+            console: console,
+            engine: Engine::Interpreter,
+            blocks: BlockEngine::new(),
+            undo: UndoLog::new(0),
+            tracer: None,
+            provenance: None,
+            symbols: Symbols::new(),
+            line_table: LineTable::new(),
+            instruction_count: 0,
+            interrupt: Arc::new(AtomicBool::new(false)),
             debug_mode: false,
         }
     }
 
+    /// Rebuilds a machine from a snapshot. The console is not part of the
+    /// snapshot; use `set_console` to attach something other than stdio.
+    pub fn from_snapshot(snapshot: Snapshot) -> Vm {
+        let mut vm = Vm::new();
+        vm.cpu = Cpu::from_parts(snapshot.state, snapshot.registers, snapshot.pc);
+        vm.bus = Bus::from_parts(Memory::from_words(snapshot.memory), Stack::from_words(snapshot.stack));
+        vm
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: *self.cpu.state(),
+            pc: self.cpu.pc(),
+            registers: *self.cpu.registers(),
+            memory: self.bus.memory().words().to_vec(),
+            stack: self.bus.stack().words().to_vec(),
+        }
+    }
+
     pub fn set_debug(&mut self, val: bool) { self.debug_mode = val; }
+    pub fn set_console(&mut self, console: Box<dyn Console>) { self.console = console; }
+
+    pub fn set_strictness(&mut self, strictness: Strictness) { self.cpu.set_strictness(strictness); }
+
+    pub fn set_engine(&mut self, engine: Engine) { self.engine = engine; }
+
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) { self.tracer = tracer; }
+
+    /// Labels for the loaded program, used to name addresses in traces and
+    /// the debugger.
+    pub fn set_symbols(&mut self, symbols: Symbols) { self.symbols = symbols; }
+    pub fn symbols(&self) -> &Symbols { &self.symbols }
+
+    /// Where the loaded program's code came from, for programs assembled
+    /// from our own sources.
+    pub fn set_line_table(&mut self, line_table: LineTable) { self.line_table = line_table; }
+    pub fn line_table(&self) -> &LineTable { &self.line_table }
+
+    /// Report register and memory reads in `Event::Executed`, for watchpoints.
+    pub fn set_record_reads(&mut self, record: bool) { self.cpu.set_record_reads(record); }
+
+    /// Active calls, innermost first.
+    pub fn backtrace(&self) -> Vec<Frame> {
+        self.cpu.call_stack().frames().iter().rev().cloned().collect()
+    }
+
+    /// A flag that, once set (say from a signal handler), stops the current
+    /// run at the next instruction boundary. It is cleared when noticed.
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> { self.interrupt.clone() }
+
+    /// Uses a flag made elsewhere, before there was a VM to ask for one.
+    pub fn set_interrupt_handle(&mut self, interrupt: Arc<AtomicBool>) { self.interrupt = interrupt; }
+
+    fn interrupted(&self) -> bool {
+        self.interrupt.load(Ordering::Relaxed) && self.interrupt.swap(false, Ordering::SeqCst)
+    }
+
+    /// Number of instructions successfully executed so far.
+    pub fn instruction_count(&self) -> u64 { self.instruction_count }
+
+    /// Keep the last `depth` instructions so they can be stepped back over.
+    /// A depth of 0 (the default) turns recording off.
+    pub fn set_undo_depth(&mut self, depth: usize) { self.undo.set_capacity(depth); }
+    pub fn undo_available(&self) -> usize { self.undo.len() }
 
-    #[cfg(test)]
     pub fn cpu(&self) -> &Cpu { &self.cpu }
+    pub fn memory(&self) -> &Memory { self.bus.memory() }
+    pub fn stack(&self) -> &Stack { self.bus.stack() }
+
+    // Changing state from outside the program can't be stepped back over
+    // correctly, so these throw away the undo log.
 
-    pub fn load_memory(&mut self, program: Vec<WORD>) {
+    pub fn set_register(&mut self, reg: usize, value: WORD) {
+        self.undo.clear();
+        let old = self.cpu.register_get(reg);
+        self.cpu.register_put(reg, value);
+        self.record_outside_write(Effect::RegisterWrite { reg: reg, old: old, new: value });
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.undo.clear();
+        self.cpu.set_pc(pc);
+    }
+
+    /// Writes one word of memory, returning false if `addr` is out of range.
+    pub fn write_memory(&mut self, addr: usize, value: WORD) -> bool {
+        self.undo.clear();
+        let old = self.bus.memory().read(addr).unwrap_or(0);
+        let written = self.bus.write_word(addr, value).is_ok();
+        if written { self.record_outside_write(Effect::MemoryWrite { addr: addr, old: old, new: value }); }
+        written
+    }
+
+    fn record_outside_write(&mut self, effect: Effect) {
+        if let Some(ref mut provenance) = self.provenance {
+            provenance.record(None, self.instruction_count, &[effect]);
+        }
+    }
+
+    /// Turns write provenance tracking on or off. Turning it on starts
+    /// with nothing known.
+    pub fn set_provenance(&mut self, on: bool) {
+        self.provenance = if on { Some(Provenance::new()) } else { None };
+    }
+
+    pub fn provenance(&self) -> Option<&Provenance> { self.provenance.as_ref() }
+    pub fn provenance_mut(&mut self) -> Option<&mut Provenance> { self.provenance.as_mut() }
+
+    pub fn load_memory(&mut self, program: Vec<WORD>) -> Result<(),VmError> {
+        let size = program.len();
         for (idx,instr) in program.into_iter().enumerate() {
             if self.debug_mode { println!("Loaded word @{:#06X}: {:#06X}", idx, instr); }
-            self.bus.write_word(idx, instr);
+            self.bus.write_word(idx, instr).map_err(|_| VmError::ProgramTooLarge { size: size })?;
         }
+        Ok(())
+    }
+
+    /// Runs until the program halts or faults. If interrupted, returns
+    /// early with the CPU still `Running`.
+    pub fn run(&mut self) -> Result<&CpuState,VmError> {
+        if self.cpu.state() == &CpuState::NotStarted { self.cpu.start()?; }
+
+        let mut res = if self.undo.is_enabled() || self.tracer.is_some() || self.provenance.is_some() {
+            self.run_recorded()
+        } else if self.engine == Engine::Blocks && self.cpu.strictness() == Strictness::Lenient {
+            self.run_blocks()
+        } else {
+            self.run_fast()
+        };
+        // an interrupt from the console leaves `in` waiting to be retried
+        if let Err(VmError::InputUnavailable { .. }) = res {
+            if self.interrupted() { res = Ok(()); }
+        }
+        self.flush();
+        res.map(move |_| self.cpu.state())
+    }
+
+    fn run_recorded(&mut self) -> Result<(),VmError> {
+        while self.cpu.is_running() && !self.interrupted() {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    // nothing is watching, so skip all of the bookkeeping in `step`
+    fn run_fast(&mut self) -> Result<(),VmError> {
+        while self.cpu.is_running() && !self.interrupted() {
+            self.cpu.step(&mut self.bus, &mut *self.console)?;
+            self.instruction_count += 1;
+        }
+        Ok(())
+    }
+
+    fn run_blocks(&mut self) -> Result<(),VmError> {
+        // memory may have changed behind the engine's back since the last run
+        self.blocks.flush();
+        while self.cpu.is_running() && !self.interrupted() {
+            let (executed, exit) = self.blocks.run_block(&mut self.cpu, &mut self.bus, &mut *self.console);
+            self.instruction_count += executed;
+            if let BlockExit::Interpret = exit {
+                if !self.cpu.is_running() { break; }
+                self.cpu.step(&mut self.bus, &mut *self.console)?;
+                self.instruction_count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes at most `budget` instructions.
+    pub fn run_for(&mut self, budget: u64) -> StopReason {
+        self.run_bounded(Some(budget), |_: &Event| false)
+    }
+
+    /// Runs until `condition` says to stop: either an address, to stop
+    /// before executing it, or a closure that is shown every `Event`. The
+    /// condition is not consulted before the first instruction, so a run
+    /// can resume from the address it last stopped at.
+    pub fn run_until<C: StopCondition>(&mut self, condition: C) -> StopReason {
+        self.run_bounded(None, condition)
+    }
+
+    pub fn run_bounded<C: StopCondition>(&mut self, budget: Option<u64>, mut condition: C) -> StopReason {
+        let reason = self.run_bounded_inner(budget, &mut condition);
+        self.flush();
+        reason
+    }
+
+    fn run_bounded_inner(&mut self, budget: Option<u64>, condition: &mut dyn StopCondition) -> StopReason {
+        let mut executed: u64 = 0;
+        loop {
+            match *self.cpu.state() {
+                CpuState::Halted => return StopReason::Halted,
+                CpuState::Error  => return StopReason::Error(VmError::InvalidState { state: CpuState::Error }),
+                _                => {},
+            }
+            if budget.map_or(false, |b| executed >= b) { return StopReason::BudgetExhausted; }
+            if self.interrupted() { return StopReason::Interrupted; }
+
+            let pc = self.cpu.pc();
+            if executed > 0 && condition.should_stop(&Event::Fetch { pc: pc }) {
+                return StopReason::BreakpointHit;
+            }
+
+            match self.step() {
+                Ok(_) => {},
+                Err(VmError::InputUnavailable { .. }) if self.interrupted() => return StopReason::Interrupted,
+                Err(VmError::InputUnavailable { .. }) => return StopReason::WaitingForInput,
+                Err(e) => return StopReason::Error(e),
+            }
+            executed += 1;
+
+            let instruction = self.cpu.last_instruction().unwrap();
+            let executed_event = Event::Executed { pc: pc, instruction: instruction, effects: self.cpu.effects(), reads: self.cpu.reads() };
+            if condition.should_stop(&executed_event) {
+                return StopReason::BreakpointHit;
+            }
+        }
+    }
+
+    /// Executes a single instruction, starting the CPU first if needed.
+    pub fn step(&mut self) -> Result<(),VmError> {
+        if self.cpu.state() == &CpuState::NotStarted { self.cpu.start()?; }
+
+        let pc = self.cpu.pc();
+        let state = *self.cpu.state();
+        let registers = *self.cpu.registers();
+        let res = self.cpu.step(&mut self.bus, &mut *self.console);
+
+        // a rewound `in` changed nothing, so there is nothing to undo
+        if self.undo.is_enabled() && (res.is_ok() || self.cpu.state() != &state) {
+            self.undo.record(UndoRecord { pc: pc, count: self.instruction_count, state: state, effects: self.cpu.effects().to_vec() });
+        }
+
+        if res.is_ok() {
+            if let Some(ref mut tracer) = self.tracer {
+                let instruction = self.cpu.last_instruction().unwrap();
+                if tracer.wants(pc, instruction.0) {
+                    let symbol = self.symbols.describe(pc);
+                    tracer.record(self.instruction_count, pc, symbol, instruction, &registers, self.cpu.effects())
+                        .map_err(|e| VmError::Trace { message: e.to_string() })?;
+                }
+            }
+            if let Some(ref mut provenance) = self.provenance {
+                provenance.record(Some(pc), self.instruction_count, self.cpu.effects());
+            }
+            self.instruction_count += 1;
+        }
+        res
+    }
+
+    fn flush(&mut self) {
+        let _ = self.console.flush();
+        if let Some(ref mut tracer) = self.tracer { let _ = tracer.flush(); }
+    }
+
+    /// Undoes up to `count` instructions, returning how many were undone.
+    /// Console output already written and input already consumed are not
+    /// given back.
+    pub fn step_back(&mut self, count: usize) -> usize {
+        for undone in 0..count {
+            match self.undo.pop() {
+                Some(record) => {
+                    self.cpu.revert(&mut self.bus, &record);
+                    self.instruction_count = record.count;
+                },
+                None => { self.forget_undone(); return undone; },
+            }
+        }
+        self.forget_undone();
+        count
+    }
+
+    fn forget_undone(&mut self) {
+        if let Some(ref mut provenance) = self.provenance { provenance.forget_from(self.instruction_count); }
     }
 
-    pub fn run(&mut self) -> &super::cpu::CpuState {
-        self.cpu.start();
-        while self.cpu.is_running() {
-            self.cpu.step(&mut self.bus, self.debug_mode);
+    /// Undoes instructions until the next one to execute is at `pc`.
+    /// Returns false, having undone the whole log, if `pc` was never reached.
+    pub fn run_back_to(&mut self, pc: usize) -> bool {
+        while let Some(record) = self.undo.pop() {
+            self.cpu.revert(&mut self.bus, &record);
+            self.instruction_count = record.count;
+            if self.cpu.pc() == pc { self.forget_undone(); return true; }
         }
-        self.cpu.state()
+        self.forget_undone();
+        false
     }
 }
\ No newline at end of file